@@ -2,17 +2,20 @@ fn main() {
     use std::{env, fs, path::PathBuf};
 
     let ld = PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("linker.ld");
-    fs::write(&ld, LINKER).unwrap();
+    // 链接地址与 SBI 的特权软件入口一致，默认 0x80200000
+    let base = env::var("KERNEL_BASE").unwrap_or_else(|_| "0x80200000".into());
+    fs::write(&ld, LINKER.replace("{KERNEL_BASE}", &base)).unwrap();
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-env-changed=LOG");
+    println!("cargo:rerun-if-env-changed=KERNEL_BASE");
     println!("cargo:rustc-link-arg=-T{}", ld.display());
 }
 
-const LINKER: &[u8] = b"
+const LINKER: &str = "
 OUTPUT_ARCH(riscv)
 ENTRY(_start)
 MEMORY {
-    DRAM : ORIGIN = 0x80200000, LENGTH = 64M
+    DRAM : ORIGIN = {KERNEL_BASE}, LENGTH = 64M
 }
 SECTIONS {
     .text : {
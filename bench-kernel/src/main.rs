@@ -99,6 +99,37 @@ extern "C" fn rust_main(hartid: usize, _dtb_pa: usize) -> ! {
     let t1 = time::read();
     log::info!("ipi duration = {}", t1 - t0);
 
+    // 打开定时器中断
+    unsafe { sie::set_stimer() };
+    // 测试定时器中断响应延迟
+    let t0 = time::read();
+    for _ in 0..100_0000 {
+        unsafe {
+            sstatus::set_sie();
+            core::arch::asm!(
+                "   la    {0}, 1f
+                    csrw  stvec, {0}
+                    ecall
+                 0: wfi
+                    j 0b
+                 .align 2
+                 1:
+                ",
+                out(reg) _,
+                in("a7") 0x54494D45,
+                in("a6") 0,
+                inlateout("a0") 0usize => _,
+                lateout("a1") _,
+                options(nomem),
+            );
+        }
+        // 清除定时器中断
+        sbi_rt::set_timer(u64::MAX);
+    }
+
+    let t1 = time::read();
+    log::info!("timer duration = {}", t1 - t0);
+
     system_reset(Shutdown, NoReason);
     unreachable!()
 }
@@ -1,18 +1,66 @@
 fn main() {
     use std::{env, fs, path::PathBuf};
 
-    let ld = &PathBuf::from(env::var_os("OUT_DIR").unwrap()).join("linker.ld");
-    fs::write(ld, LINKER).unwrap();
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    // 支持的硬件线程数量决定 SBI 占用的内存
+    let num_hart_max = env::var("NUM_HART_MAX").map_or(DEFAULT_NUM_HART_MAX, |n| {
+        n.parse::<usize>()
+            .ok()
+            .filter(|n| (1..=512).contains(n))
+            .expect("NUM_HART_MAX should be in 1..=512")
+    });
+    let len_firmware = firmware_len(num_hart_max);
+    let ld = &out.join("linker.ld");
+    fs::write(
+        ld,
+        LINKER
+            .replace("{LEN_FIRMWARE}", &format!("{len_firmware:#x}"))
+            .replace(
+                "{FIRMWARE_END}",
+                &format!("{:#x}", 0x8000_0000 + len_firmware),
+            ),
+    )
+    .unwrap();
+    println!("cargo:rustc-env=NUM_HART_MAX={num_hart_max}");
+    println!("cargo:rustc-env=LEN_FIRMWARE={len_firmware}");
+    // 内嵌特权软件
+    let incbin = match env::var("PAYLOAD") {
+        Ok(payload) => {
+            let payload = fs::canonicalize(payload).expect("payload not found");
+            println!("cargo:rerun-if-changed={}", payload.display());
+            format!("    .incbin {:?}\n", payload.display().to_string())
+        }
+        Err(_) => String::new(),
+    };
+    fs::write(
+        out.join("payload.S"),
+        format!(
+            "    .section .payload, \"ax\", @progbits
+    .globl payload_start, payload_end
+payload_start:
+{incbin}payload_end:
+"
+        ),
+    )
+    .unwrap();
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=firmware_len.rs");
     println!("cargo:rerun-if-env-changed=LOG");
+    println!("cargo:rerun-if-env-changed=SUPERVISOR_ENTRY");
+    println!("cargo:rerun-if-env-changed=NUM_HART_MAX");
+    println!("cargo:rerun-if-env-changed=PAYLOAD");
+    println!("cargo:rerun-if-env-changed=RESERVE_CONSOLE");
     println!("cargo:rustc-link-arg=-T{}", ld.display());
 }
 
-const LINKER: &[u8] = b"
+include!("firmware_len.rs");
+
+const LINKER: &str = "
 OUTPUT_ARCH(riscv)
 ENTRY(_start)
 MEMORY {
-    DRAM : ORIGIN = 0x80000000, LENGTH = 2M
+    DRAM : ORIGIN = 0x80000000, LENGTH = {LEN_FIRMWARE}
+    PAYLOAD : ORIGIN = {FIRMWARE_END}, LENGTH = 1024M
 }
 SECTIONS {
     .text : {
@@ -36,6 +84,9 @@ SECTIONS {
         . = ALIGN(8);
         ebss = .;
     } > DRAM
+    .payload : {
+        *(.payload)
+    } > PAYLOAD
     /DISCARD/ : {
         *(.eh_frame)
     }
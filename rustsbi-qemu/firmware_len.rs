@@ -0,0 +1,13 @@
+// 构建脚本和 xtask 以 `include!` 共用这里的计算，测试内核据此链接到 SBI 之后。
+
+/// 默认支持的硬件线程数量。
+const DEFAULT_NUM_HART_MAX: usize = 8;
+
+/// SBI 占用的内存长度。
+///
+/// 为代码和数据预留 512KiB，每个硬件线程和启动过程各占用 16KiB 栈，按 2MiB 对齐。
+fn firmware_len(num_hart_max: usize) -> usize {
+    const ALIGN: usize = 2 << 20;
+    let len = (512 << 10) + (num_hart_max + 1) * (16 << 10);
+    (len + ALIGN - 1) & !(ALIGN - 1)
+}
@@ -1,25 +1,51 @@
-use crate::{hart_id, trap_stack::remote_hsm};
+use crate::{
+    hart_id, pmu,
+    riscv_spec::{menvcfg, stimecmp},
+    trap_stack::{remote_hsm, remote_mailbox, select_harts},
+};
 use aclint::SifiveClint;
 use core::{
     ptr::null_mut,
-    sync::atomic::{AtomicPtr, Ordering},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
 };
 use rustsbi::{HartMask, Ipi, SbiRet, Timer};
+use sbi_spec::pmu::firmware_event;
 
 pub(crate) struct Clint;
 
 pub(crate) static CLINT: AtomicPtr<SifiveClint> = AtomicPtr::new(null_mut());
 
-pub(crate) fn init(base: usize) {
+/// 是否使用 Sstc 扩展的 `stimecmp` 实现定时器。
+static SSTC: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init(base: usize, sstc: bool) {
+    // 设备树声明了 Sstc 时，再确认 menvcfg.STCE 可写
+    if sstc {
+        menvcfg::set(menvcfg::STCE);
+        SSTC.store(menvcfg::read() & menvcfg::STCE != 0, Ordering::Relaxed);
+    }
     CLINT.store(base as _, Ordering::Release);
 }
 
+/// 判断是否使用 Sstc 扩展。
+///
+/// 使用 Sstc 时特权软件直接设置 `stimecmp`，不需要 machine timer 中断代理。
+#[inline]
+pub fn sstc_enabled() -> bool {
+    SSTC.load(Ordering::Relaxed)
+}
+
 impl Ipi for Clint {
     #[inline]
     fn send_ipi(&self, hart_mask: HartMask) -> SbiRet {
-        for i in 0..crate::NUM_HART_MAX {
-            if hart_mask.has_bit(i) && remote_hsm(i).map_or(false, |hsm| hsm.allow_ipi()) {
+        let Some(harts) = select_harts(hart_mask) else {
+            return SbiRet::invalid_param();
+        };
+        for i in harts {
+            if remote_hsm(i).map_or(false, |hsm| hsm.allow_ipi()) {
+                remote_mailbox(i).unwrap().post_ipi();
                 set_msip(i);
+                pmu::record(firmware_event::IPI_SENT);
             }
         }
         SbiRet::success(0)
@@ -29,9 +55,14 @@ impl Ipi for Clint {
 impl Timer for Clint {
     #[inline]
     fn set_timer(&self, time_value: u64) {
-        unsafe {
-            riscv::register::mip::clear_stimer();
-            (*CLINT.load(Ordering::Relaxed)).write_mtimecmp(hart_id(), time_value);
+        pmu::record(firmware_event::SET_TIMER);
+        if sstc_enabled() {
+            stimecmp::write(time_value);
+        } else {
+            unsafe {
+                riscv::register::mip::clear_stimer();
+                (*CLINT.load(Ordering::Relaxed)).write_mtimecmp(hart_id(), time_value);
+            }
         }
     }
 }
@@ -46,12 +77,21 @@ pub fn clear_msip() {
     unsafe { &*CLINT.load(Ordering::Relaxed) }.clear_msip(hart_id());
 }
 
+#[inline]
+pub fn read_mtime() -> u64 {
+    unsafe { &*CLINT.load(Ordering::Relaxed) }.read_mtime()
+}
+
 #[inline]
 pub fn clear() {
     loop {
         if let Some(clint) = unsafe { CLINT.load(Ordering::Relaxed).as_ref() } {
             clint.clear_msip(hart_id());
             clint.write_mtimecmp(hart_id(), u64::MAX);
+            if sstc_enabled() {
+                menvcfg::set(menvcfg::STCE);
+                stimecmp::write(u64::MAX);
+            }
             break;
         } else {
             continue;
@@ -0,0 +1,224 @@
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+use spin::Mutex;
+
+/// 控制台设备。
+pub(crate) trait ConsoleDevice: Sync {
+    /// 从设备接收一个字符，没有输入时返回 `None`。
+    fn receive(&self) -> Option<u8>;
+
+    /// 向设备发送一个字符，设备忙时返回 `false`。
+    fn transmit(&self, c: u8) -> bool;
+
+    /// 设置设备的接收中断和发送中断，不支持中断的设备忽略。
+    fn set_interrupts(&self, _rx: bool, _tx: bool) {}
+}
+
+/// 控制台设备和发送缓冲区。
+///
+/// 每次持有锁时都会收取接收的字符、尽量发送缓冲的字符，持有锁期间不会等待设备。
+static CONSOLE: Mutex<Console> = Mutex::new(Console {
+    device: None,
+    tx: TxFifo {
+        head: 0,
+        len: 0,
+        buf: [0; TxFifo::CAPACITY],
+    },
+});
+
+/// 接收缓冲区。
+static RX: RxRing = RxRing::new();
+
+/// 是否由设备中断收取字符。
+static INTERRUPT: AtomicBool = AtomicBool::new(false);
+
+/// 设置控制台设备，设置之前写入的字符被丢弃。
+pub(crate) fn init(device: &'static dyn ConsoleDevice) {
+    CONSOLE.lock().device = Some(device);
+}
+
+/// 打开控制台设备的接收中断。
+///
+/// 中断控制器就绪后调用，之后发送缓冲区中剩余的字符也由中断发出。
+pub(crate) fn enable_interrupt() {
+    INTERRUPT.store(true, Ordering::Relaxed);
+    CONSOLE.lock().poll();
+}
+
+/// 处理控制台设备的中断。
+///
+/// 其他 hart 正在访问设备时直接返回，持有锁的 hart 会完成收发。
+#[inline]
+pub(crate) fn handle_interrupt() {
+    if let Some(mut console) = CONSOLE.try_lock() {
+        console.poll();
+    }
+}
+
+/// 从接收缓冲区读取字符到 `buf`，返回读取的字符数，不等待输入。
+pub(crate) fn read(buf: &mut [u8]) -> usize {
+    handle_interrupt();
+    let mut count = 0;
+    for c in buf {
+        match RX.pop() {
+            Some(b) => *c = b,
+            None => break,
+        }
+        count += 1;
+    }
+    count
+}
+
+/// 从 `buf` 写入字符到发送缓冲区，返回写入的字符数，发送缓冲区满时不等待。
+pub(crate) fn write(buf: &[u8]) -> usize {
+    let mut console = CONSOLE.lock();
+    let count = console.tx.push(buf);
+    console.poll();
+    count
+}
+
+/// 将 `buf` 全部写入发送缓冲区。
+///
+/// 发送缓冲区满时在锁外等待，不妨碍其他 hart 读取。
+pub(crate) fn write_all(mut buf: &[u8]) {
+    loop {
+        buf = &buf[write(buf)..];
+        if buf.is_empty() {
+            break;
+        }
+        spin_loop();
+    }
+}
+
+/// 等待发送缓冲区中的字符全部交给设备。
+///
+/// 关机和重启前调用，避免丢失最后的输出。
+pub(crate) fn flush() {
+    loop {
+        let mut console = CONSOLE.lock();
+        console.poll();
+        if console.tx.len == 0 {
+            break;
+        }
+        drop(console);
+        spin_loop();
+    }
+}
+
+struct Console {
+    device: Option<&'static dyn ConsoleDevice>,
+    tx: TxFifo,
+}
+
+impl Console {
+    /// 收取设备接收的所有字符，并发送设备能接受的缓冲字符。
+    fn poll(&mut self) {
+        let Some(device) = self.device else {
+            // 没有控制台设备时丢弃输出
+            self.tx.len = 0;
+            return;
+        };
+        while let Some(c) = device.receive() {
+            // 接收缓冲区满时丢弃新字符
+            RX.push(c);
+        }
+        while let Some(c) = self.tx.peek() {
+            if !device.transmit(c) {
+                break;
+            }
+            self.tx.pop();
+        }
+        if INTERRUPT.load(Ordering::Relaxed) {
+            // 发送缓冲区有剩余时等待设备可以发送的中断
+            device.set_interrupts(true, self.tx.len > 0);
+        }
+    }
+}
+
+/// 发送缓冲区。
+struct TxFifo {
+    head: usize,
+    len: usize,
+    buf: [u8; TxFifo::CAPACITY],
+}
+
+impl TxFifo {
+    const CAPACITY: usize = 1024;
+
+    fn push(&mut self, bytes: &[u8]) -> usize {
+        let count = bytes.len().min(Self::CAPACITY - self.len);
+        for &c in &bytes[..count] {
+            self.buf[(self.head + self.len) % Self::CAPACITY] = c;
+            self.len += 1;
+        }
+        count
+    }
+
+    #[inline]
+    fn peek(&self) -> Option<u8> {
+        (self.len > 0).then(|| self.buf[self.head])
+    }
+
+    #[inline]
+    fn pop(&mut self) {
+        self.head = (self.head + 1) % Self::CAPACITY;
+        self.len -= 1;
+    }
+}
+
+/// 接收缓冲区。
+///
+/// 只有持有串口锁的 hart 写入，任何 hart 都可以无锁读取。
+struct RxRing {
+    /// 读取的字符总数。
+    head: AtomicUsize,
+    /// 写入的字符总数。
+    tail: AtomicUsize,
+    buf: [AtomicU8; RxRing::CAPACITY],
+}
+
+impl RxRing {
+    const CAPACITY: usize = 256;
+
+    const fn new() -> Self {
+        Self {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            buf: [const { AtomicU8::new(0) }; Self::CAPACITY],
+        }
+    }
+
+    /// 写入一个字符，缓冲区满时返回 `false`。
+    fn push(&self, c: u8) -> bool {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == Self::CAPACITY {
+            return false;
+        }
+        self.buf[tail % Self::CAPACITY].store(c, Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    /// 读取一个字符，缓冲区空时返回 `None`。
+    fn pop(&self) -> Option<u8> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            if head == self.tail.load(Ordering::Acquire) {
+                return None;
+            }
+            // 写入方在读取方移动 `head` 之前不会覆盖这个位置
+            let c = self.buf[head % Self::CAPACITY].load(Ordering::Relaxed);
+            match self.head.compare_exchange_weak(
+                head,
+                head.wrapping_add(1),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(c),
+                Err(current) => head = current,
+            }
+        }
+    }
+}
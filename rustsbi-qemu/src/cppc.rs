@@ -0,0 +1,117 @@
+use crate::{clint, trap_stack::local_cppc};
+use rustsbi::SbiRet;
+
+pub(crate) struct Cppc;
+
+/// 标准 CPPC 寄存器编号。
+mod reg {
+    pub const HIGHEST_PERFORMANCE: u32 = 0x00;
+    pub const NOMINAL_PERFORMANCE: u32 = 0x01;
+    pub const LOWEST_PERFORMANCE: u32 = 0x03;
+    pub const DESIRED_PERFORMANCE: u32 = 0x05;
+    pub const REFERENCE_PERFORMANCE_COUNTER: u32 = 0x0b;
+    pub const DELIVERED_PERFORMANCE_COUNTER: u32 = 0x0c;
+    pub const CPPC_ENABLE: u32 = 0x0e;
+    /// 最后一个标准寄存器。
+    pub const LAST: u32 = 0x14;
+    /// 平台自定义寄存器的起始编号。
+    pub const IMPLEMENTATION_SPECIFIC: u32 = 0x8000_0000;
+}
+
+/// 模拟的性能等级。
+const PERF_HIGHEST: u32 = 100;
+const PERF_NOMINAL: u32 = 100;
+const PERF_LOWEST: u32 = 10;
+
+/// 每个 hart 的可写 CPPC 寄存器。
+pub(crate) struct CppcState {
+    desired: u32,
+    enable: u32,
+}
+
+impl CppcState {
+    pub const fn new() -> Self {
+        Self {
+            desired: PERF_NOMINAL,
+            enable: 0,
+        }
+    }
+}
+
+/// 寄存器的宽度和是否可写。
+///
+/// 未实现的标准寄存器和平台自定义寄存器返回 `Ok(None)`，保留的编号返回错误。
+fn lookup(reg_id: u32) -> Result<Option<(usize, bool)>, SbiRet> {
+    use reg::*;
+    match reg_id {
+        HIGHEST_PERFORMANCE | NOMINAL_PERFORMANCE | LOWEST_PERFORMANCE => Ok(Some((32, false))),
+        DESIRED_PERFORMANCE | CPPC_ENABLE => Ok(Some((32, true))),
+        REFERENCE_PERFORMANCE_COUNTER | DELIVERED_PERFORMANCE_COUNTER => Ok(Some((64, false))),
+        0..=LAST | IMPLEMENTATION_SPECIFIC.. => Ok(None),
+        _ => Err(SbiRet::invalid_param()),
+    }
+}
+
+impl rustsbi::Cppc for Cppc {
+    #[inline]
+    fn probe(&self, reg_id: u32) -> SbiRet {
+        match lookup(reg_id) {
+            Ok(Some((width, _))) => SbiRet::success(width),
+            Ok(None) => SbiRet::success(0),
+            Err(e) => e,
+        }
+    }
+
+    fn read(&self, reg_id: u32) -> SbiRet {
+        use reg::*;
+        match lookup(reg_id) {
+            Ok(Some(_)) => {}
+            Ok(None) => return SbiRet::not_supported(),
+            Err(e) => return e,
+        }
+        let state = local_cppc();
+        let value = match reg_id {
+            HIGHEST_PERFORMANCE => PERF_HIGHEST as _,
+            NOMINAL_PERFORMANCE => PERF_NOMINAL as _,
+            LOWEST_PERFORMANCE => PERF_LOWEST as _,
+            DESIRED_PERFORMANCE => state.desired as _,
+            CPPC_ENABLE => state.enable as _,
+            // 参考计数器以额定性能计数，即 mtime
+            REFERENCE_PERFORMANCE_COUNTER => clint::read_mtime() as _,
+            // 实际性能计数器是此 hart 的 mcycle
+            DELIVERED_PERFORMANCE_COUNTER => riscv::register::mcycle::read64() as _,
+            _ => unreachable!(),
+        };
+        SbiRet::success(value)
+    }
+
+    #[inline]
+    fn read_hi(&self, reg_id: u32) -> SbiRet {
+        // RV64 上读取的高 32 位总是 0
+        match lookup(reg_id) {
+            Ok(Some(_)) => SbiRet::success(0),
+            Ok(None) => SbiRet::not_supported(),
+            Err(e) => e,
+        }
+    }
+
+    fn write(&self, reg_id: u32, val: u64) -> SbiRet {
+        use reg::*;
+        match lookup(reg_id) {
+            Ok(Some((_, true))) => {}
+            Ok(Some((_, false))) => return SbiRet::denied(),
+            Ok(None) => return SbiRet::not_supported(),
+            Err(e) => return e,
+        }
+        let state = local_cppc();
+        match reg_id {
+            // 超出范围的期望性能按最近的性能等级处理
+            DESIRED_PERFORMANCE => {
+                state.desired = val.clamp(PERF_LOWEST as _, PERF_HIGHEST as _) as _
+            }
+            CPPC_ENABLE => state.enable = (val & 1) as _,
+            _ => unreachable!(),
+        }
+        SbiRet::success(0)
+    }
+}
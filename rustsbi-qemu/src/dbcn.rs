@@ -1,13 +1,12 @@
-﻿use crate::uart16550;
-use core::ops::Range;
+﻿use crate::{console, constants::NUM_MEMORY_REGION_MAX, device_tree::RangeList};
 use rustsbi::{Console, Physical, SbiRet};
 use spin::Once;
 
-pub(crate) struct DBCN(Range<usize>);
+pub(crate) struct DBCN(RangeList<NUM_MEMORY_REGION_MAX>);
 
 static INSTANCE: Once<DBCN> = Once::new();
 
-pub(crate) fn init(memory: Range<usize>) {
+pub(crate) fn init(memory: RangeList<NUM_MEMORY_REGION_MAX>) {
     INSTANCE.call_once(|| DBCN(memory));
 }
 
@@ -19,9 +18,9 @@ impl Console for DBCN {
     fn write(&self, bytes: Physical<&[u8]>) -> SbiRet {
         let start = bytes.phys_addr_lo();
         let end = start + bytes.num_bytes();
-        if self.0.contains(&start) && self.0.contains(&(end - 1)) {
+        if self.0.contains_range(start, end) {
             let buf = unsafe { core::slice::from_raw_parts(start as *const u8, bytes.num_bytes()) };
-            SbiRet::success(uart16550::UART.lock().get().write(buf))
+            SbiRet::success(console::write(buf))
         } else {
             SbiRet::invalid_param()
         }
@@ -30,10 +29,10 @@ impl Console for DBCN {
     fn read(&self, bytes: Physical<&mut [u8]>) -> SbiRet {
         let start = bytes.phys_addr_lo();
         let end = start + bytes.num_bytes();
-        if self.0.contains(&start) && self.0.contains(&(end - 1)) {
+        if self.0.contains_range(start, end) {
             let buf =
                 unsafe { core::slice::from_raw_parts_mut(start as *mut u8, bytes.num_bytes()) };
-            SbiRet::success(uart16550::UART.lock().get().read(buf))
+            SbiRet::success(console::read(buf))
         } else {
             SbiRet::invalid_param()
         }
@@ -41,11 +40,7 @@ impl Console for DBCN {
 
     #[inline]
     fn write_byte(&self, byte: u8) -> SbiRet {
-        let uart = uart16550::UART.lock();
-        loop {
-            if uart.get().write(&[byte]) == 1 {
-                return SbiRet::success(0);
-            }
-        }
+        console::write_all(&[byte]);
+        SbiRet::success(0)
     }
 }
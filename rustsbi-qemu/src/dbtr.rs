@@ -0,0 +1,373 @@
+use crate::{
+    riscv_spec::{tdata1, tdata2, tdata3, tselect},
+    supervisor_accessible,
+    trap_stack::local_dbtr,
+    unprivileged::trap_handler,
+};
+use core::arch::asm;
+use riscv::register::medeleg;
+use rustsbi::SbiRet;
+
+/// 调试触发器扩展号 "DBTR"。
+///
+/// 特权软件通过共享内存安装地址和数据匹配触发器，命中时引起断点异常。
+pub(crate) const EID_DBTR: usize = 0x4442_5452;
+const NUM_TRIGGERS: usize = 0;
+const SET_SHMEM: usize = 1;
+const READ_TRIGGERS: usize = 2;
+const INSTALL_TRIGGERS: usize = 3;
+const UPDATE_TRIGGERS: usize = 4;
+const UNINSTALL_TRIGGERS: usize = 5;
+const ENABLE_TRIGGERS: usize = 6;
+const DISABLE_TRIGGERS: usize = 7;
+
+/// 每个 hart 最多使用的触发器数量。
+const NUM_TRIGGER_MAX: usize = 8;
+
+/// `tdata1` 的字段。
+mod tdata {
+    /// 触发器类型。
+    pub const TYPE_SHIFT: usize = usize::BITS as usize - 4;
+    pub const TYPE_MCONTROL: usize = 2;
+    pub const TYPE_MCONTROL6: usize = 6;
+    /// 只有调试模式可以修改。
+    pub const DMODE: usize = 1 << (usize::BITS - 5);
+    /// 命中时的动作，0 表示引起断点异常。
+    pub const ACTION: usize = 0xf << 12;
+    pub const M: usize = 1 << 6;
+    /// 在这些特权级下触发，对 `mcontrol` 来说 VS 和 VU 位总是 0。
+    pub const MODES: usize = 1 << 3 | 1 << 4 | 1 << 23 | 1 << 24;
+}
+
+/// 共享内存中 `tstate` 的 MAPPED 位。
+const TSTATE_MAPPED: usize = 1 << 0;
+
+/// SBI v2.0 定义的 `SBI_ERR_NO_SHMEM`。
+const ERR_NO_SHMEM: usize = -9isize as usize;
+
+/// 特权软件安装的触发器。
+///
+/// 每个触发器对应同序号的硬件触发器。
+#[derive(Clone, Copy)]
+struct Trigger {
+    /// 硬件触发器支持的类型，即 `tinfo` 的低 16 位。
+    info: usize,
+    mapped: bool,
+    enabled: bool,
+    tdata1: usize,
+    tdata2: usize,
+    tdata3: usize,
+}
+
+impl Trigger {
+    const EMPTY: Self = Self {
+        info: 0,
+        mapped: false,
+        enabled: false,
+        tdata1: 0,
+        tdata2: 0,
+        tdata3: 0,
+    };
+
+    /// 将触发器写入第 `i` 个硬件触发器，停用的触发器清除特权级位。
+    fn load(&self, i: usize) {
+        tselect::write(i);
+        tdata1::write(0);
+        if self.mapped {
+            tdata2::write(self.tdata2);
+            tdata3::write(self.tdata3);
+            tdata1::write(if self.enabled {
+                self.tdata1
+            } else {
+                self.tdata1 & !tdata::MODES
+            });
+        }
+    }
+}
+
+/// 每个 hart 的触发器状态。
+pub(crate) struct DbtrState {
+    /// 硬件触发器数量。
+    num: usize,
+    /// 共享内存的物理地址，未设置时为 `None`。
+    shmem: Option<usize>,
+    triggers: [Trigger; NUM_TRIGGER_MAX],
+}
+
+impl DbtrState {
+    pub const fn new() -> Self {
+        Self {
+            num: 0,
+            shmem: None,
+            triggers: [Trigger::EMPTY; NUM_TRIGGER_MAX],
+        }
+    }
+}
+
+/// 选择第 `i` 个硬件触发器，返回它支持的类型；不存在时返回 `None`。
+///
+/// 未实现 Sdtrig 扩展时访问 `tselect` 引起非法指令异常，临时替换 `mtvec` 捕获。
+/// 汇编器不认识 `tinfo`，以编号 0x7a4 访问。
+fn probe(i: usize) -> Option<usize> {
+    const NO_TRAP: usize = usize::MAX;
+    let (selected, info, cause): (usize, usize, usize);
+    unsafe {
+        asm!(
+            "   csrr  {mepc},  mepc
+                csrrw {mtvec}, mtvec, {mtvec}
+                csrw  tselect, {i}
+                csrr  {sel},   tselect
+                csrr  {info},  0x7a4
+                csrw  mtvec,   {mtvec}
+                csrw  mepc,    {mepc}
+            ",
+            mepc  = out(reg) _,
+            mtvec = inout(reg) trap_handler as usize => _,
+            i     = in(reg) i,
+            sel   = inout(reg) !i => selected,
+            info  = inout(reg) 0usize => info,
+            inout("t0") NO_TRAP => cause,
+            out("t1") _,
+            out("t2") _,
+        )
+    };
+    if selected != i {
+        return None;
+    }
+    // 没有 `tinfo` 时只支持当前类型
+    let info = if cause == NO_TRAP {
+        info & 0xffff
+    } else {
+        1 << (tdata1::read() >> tdata::TYPE_SHIFT)
+    };
+    // 只支持类型 0 表示触发器不存在
+    (info & !1 != 0).then_some(info)
+}
+
+/// 探测此 hart 的硬件触发器并全部卸载。
+///
+/// 特权软件启动时调用。
+pub(crate) fn reset() {
+    let state = local_dbtr();
+    *state = DbtrState::new();
+    while state.num < NUM_TRIGGER_MAX {
+        let Some(info) = probe(state.num) else {
+            break;
+        };
+        state.triggers[state.num].info = info;
+        state.triggers[state.num].load(state.num);
+        state.num += 1;
+    }
+    // 触发器引起的断点异常交给特权软件处理
+    unsafe { medeleg::set_breakpoint() };
+}
+
+/// 将保存的触发器重新写入硬件。
+///
+/// 从不可恢复挂起中唤醒时调用。
+pub(crate) fn restore() {
+    let state = local_dbtr();
+    for (i, trigger) in state.triggers[..state.num].iter().enumerate() {
+        trigger.load(i);
+    }
+}
+
+/// 处理调试触发器扩展的调用。
+pub(crate) fn handle(fid: usize, [a0, a1, a2]: [usize; 3]) -> SbiRet {
+    match fid {
+        NUM_TRIGGERS => num_triggers(a0),
+        SET_SHMEM => set_shmem(a0, a1, a2),
+        READ_TRIGGERS => read_triggers(a0, a1),
+        INSTALL_TRIGGERS => install_triggers(a0),
+        UPDATE_TRIGGERS => update_triggers(a0),
+        UNINSTALL_TRIGGERS => select(a0, a1, |t| {
+            *t = Trigger {
+                info: t.info,
+                ..Trigger::EMPTY
+            }
+        }),
+        ENABLE_TRIGGERS => select(a0, a1, |t| t.enabled = true),
+        DISABLE_TRIGGERS => select(a0, a1, |t| t.enabled = false),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// 支持 `tdata1` 指定类型的触发器数量，`tdata1` 为 0 时返回所有触发器数量。
+fn num_triggers(tdata1: usize) -> SbiRet {
+    let state = local_dbtr();
+    let triggers = &state.triggers[..state.num];
+    if tdata1 == 0 {
+        return SbiRet::success(triggers.len());
+    }
+    let ty = tdata1 >> tdata::TYPE_SHIFT;
+    SbiRet::success(triggers.iter().filter(|t| t.info & (1 << ty) != 0).count())
+}
+
+fn set_shmem(lo: usize, hi: usize, flags: usize) -> SbiRet {
+    if flags != 0 {
+        return SbiRet::invalid_param();
+    }
+    // 全 1 表示停用
+    if lo == usize::MAX && hi == usize::MAX {
+        local_dbtr().shmem = None;
+        return SbiRet::success(0);
+    }
+    if lo % core::mem::size_of::<usize>() != 0 {
+        return SbiRet::invalid_param();
+    }
+    if hi != 0 || !supervisor_accessible(lo, core::mem::size_of::<usize>()) {
+        return SbiRet::invalid_address();
+    }
+    local_dbtr().shmem = Some(lo);
+    SbiRet::success(0)
+}
+
+/// 共享内存中的前 `count` 项，每项 4 个字。
+fn entries(count: usize) -> Result<&'static mut [[usize; 4]], SbiRet> {
+    let Some(shmem) = local_dbtr().shmem else {
+        return Err(SbiRet {
+            error: ERR_NO_SHMEM,
+            value: 0,
+        });
+    };
+    let Some(len) = count.checked_mul(core::mem::size_of::<[usize; 4]>()) else {
+        return Err(SbiRet::invalid_param());
+    };
+    if supervisor_accessible(shmem, len) {
+        Ok(unsafe { core::slice::from_raw_parts_mut(shmem as *mut _, count) })
+    } else {
+        Err(SbiRet::invalid_param())
+    }
+}
+
+/// 检查特权软件给出的触发器配置。
+///
+/// 只接受引起断点异常的地址和数据匹配触发器，且不能在 M 态触发。
+fn check(trigger: &Trigger, tdata1: usize) -> bool {
+    let ty = tdata1 >> tdata::TYPE_SHIFT;
+    matches!(ty, tdata::TYPE_MCONTROL | tdata::TYPE_MCONTROL6)
+        && trigger.info & (1 << ty) != 0
+        && tdata1 & (tdata::DMODE | tdata::ACTION | tdata::M) == 0
+}
+
+fn read_triggers(base: usize, count: usize) -> SbiRet {
+    let state = local_dbtr();
+    if base.checked_add(count).map_or(true, |end| end > state.num) {
+        return SbiRet::invalid_param();
+    }
+    let entries = match entries(count) {
+        Ok(entries) => entries,
+        Err(e) => return e,
+    };
+    for (entry, trigger) in entries.iter_mut().zip(&state.triggers[base..]) {
+        let tstate = if trigger.mapped { TSTATE_MAPPED } else { 0 };
+        *entry = [tstate, trigger.tdata1, trigger.tdata2, trigger.tdata3];
+    }
+    SbiRet::success(0)
+}
+
+/// 安装触发器，每项输入 `tdata1`、`tdata2` 和 `tdata3`，输出分配的序号。
+///
+/// 任何一项失败时都不安装，返回值是失败的项。
+fn install_triggers(count: usize) -> SbiRet {
+    let entries = match entries(count) {
+        Ok(entries) => entries,
+        Err(e) => return e,
+    };
+    let state = local_dbtr();
+    let num = state.num;
+    if count > num {
+        return SbiRet::failed();
+    }
+    // 先为每一项分配触发器
+    let mut taken = [false; NUM_TRIGGER_MAX];
+    let mut assigned = [0; NUM_TRIGGER_MAX];
+    for (i, entry) in entries.iter().enumerate() {
+        let tdata1 = entry[0];
+        if !state.triggers[..num].iter().any(|t| check(t, tdata1)) {
+            return SbiRet {
+                error: SbiRet::invalid_param().error,
+                value: i,
+            };
+        }
+        let free = (0..num)
+            .find(|&j| !taken[j] && !state.triggers[j].mapped && check(&state.triggers[j], tdata1));
+        match free {
+            Some(j) => {
+                taken[j] = true;
+                assigned[i] = j;
+            }
+            None => {
+                return SbiRet {
+                    error: SbiRet::failed().error,
+                    value: i,
+                }
+            }
+        }
+    }
+    for (entry, &j) in entries.iter_mut().zip(&assigned) {
+        let [tdata1, tdata2, tdata3, _] = *entry;
+        let trigger = &mut state.triggers[j];
+        *trigger = Trigger {
+            info: trigger.info,
+            mapped: true,
+            enabled: true,
+            tdata1,
+            tdata2,
+            tdata3,
+        };
+        trigger.load(j);
+        entry[0] = j;
+    }
+    SbiRet::success(0)
+}
+
+/// 更新已安装的触发器，每项输入序号、`tdata1`、`tdata2` 和 `tdata3`。
+///
+/// 任何一项失败时都不更新，返回值是失败的项。
+fn update_triggers(count: usize) -> SbiRet {
+    let entries = match entries(count) {
+        Ok(entries) => entries,
+        Err(e) => return e,
+    };
+    let state = local_dbtr();
+    // 项数不能超过触发器数量
+    if count > state.num {
+        return SbiRet::invalid_param();
+    }
+    for (i, &[j, tdata1, ..]) in entries.iter().enumerate() {
+        let valid = j < state.num && state.triggers[j].mapped && check(&state.triggers[j], tdata1);
+        if !valid {
+            return SbiRet {
+                error: SbiRet::invalid_param().error,
+                value: i,
+            };
+        }
+    }
+    for &[j, tdata1, tdata2, tdata3] in entries.iter() {
+        let trigger = &mut state.triggers[j];
+        trigger.tdata1 = tdata1;
+        trigger.tdata2 = tdata2;
+        trigger.tdata3 = tdata3;
+        trigger.load(j);
+    }
+    SbiRet::success(0)
+}
+
+/// 对 `base` 和 `mask` 选出的已安装触发器执行 `f`，有任何一个不存在或未安装时不执行。
+fn select(base: usize, mask: usize, f: impl Fn(&mut Trigger)) -> SbiRet {
+    let state = local_dbtr();
+    let selected = (0..usize::BITS as usize)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| base.checked_add(i));
+    for j in selected.clone() {
+        if !j.map_or(false, |j| j < state.num && state.triggers[j].mapped) {
+            return SbiRet::invalid_param();
+        }
+    }
+    for j in selected.flatten() {
+        f(&mut state.triggers[j]);
+        state.triggers[j].load(j);
+    }
+    SbiRet::success(0)
+}
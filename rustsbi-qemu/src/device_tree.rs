@@ -1,6 +1,7 @@
-﻿use core::{
+﻿use crate::constants::{NUM_HART_MAX, NUM_MEMORY_REGION_MAX};
+use core::{
     fmt::{Display, Formatter, Result},
-    ops::Range,
+    ops::{Deref, DerefMut, Range},
 };
 
 /// 从设备树采集的板信息。
@@ -8,15 +9,136 @@ pub(crate) struct BoardInfo {
     pub dtb: Range<usize>,
     pub model: StringInline<128>,
     pub smp: usize,
-    pub mem: Range<usize>,
-    pub uart: Range<usize>,
+    /// `mtime` 的频率，设备树未给出时视为 10MHz。
+    pub timebase: u64,
+    /// `cpus` 节点中的硬件线程编号，超出数量上限的硬件线程不会启动。
+    pub harts: ListInline<u32, NUM_HART_MAX>,
+    /// 按起始地址排序的所有内存区域。
+    pub mem: RangeList<NUM_MEMORY_REGION_MAX>,
+    /// 控制台设备，`/chosen/stdout-path` 未指定或找不到时选择第一个可用的设备。
+    pub console: Option<ConsoleInfo>,
+    /// 平台级中断控制器。
+    pub plic: Option<PlicInfo>,
     pub test: Range<usize>,
     pub clint: Range<usize>,
+    /// 所有硬件线程都支持 Sstc 扩展。
+    pub sstc: bool,
+    /// 所有硬件线程都支持 Svadu 扩展。
+    pub svadu: bool,
+    /// 所有硬件线程都支持 Ssnpm 扩展。
+    pub ssnpm: bool,
+    /// `/chosen` 中 `rustsbi,next-addr` 指定的特权软件入口。
+    pub next_addr: Option<usize>,
+    /// `/chosen` 中 `linux,initrd-start` 和 `linux,initrd-end` 指定的初始内存盘。
+    pub initrd: Option<Range<usize>>,
+}
+
+/// 控制台设备。
+#[derive(Clone)]
+pub(crate) struct ConsoleInfo {
+    pub kind: ConsoleKind,
+    /// 设备节点的路径。
+    pub path: StringInline<64>,
+    /// 寄存器地址，HTIF 没有寄存器。
+    pub reg: Range<usize>,
+    /// 中断号。
+    pub irq: Option<u32>,
+    /// 串口输入时钟频率。
+    pub clock_frequency: Option<u32>,
+    /// 串口当前的波特率。
+    pub current_speed: Option<u32>,
+    /// 寄存器编号左移的位数。
+    pub reg_shift: u32,
+    /// 寄存器访问宽度，单位是字节。
+    pub reg_io_width: u32,
+    /// `/chosen/stdout-path` 冒号后的串口参数，例如 `115200n8`。
+    pub options: StringInline<16>,
+}
+
+/// 支持的控制台设备种类。
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum ConsoleKind {
+    Ns16550,
+    SifiveUart,
+    Htif,
+}
+
+impl ConsoleKind {
+    /// 按 `compatible` 中的一项识别设备。
+    fn from_compatible(compatible: &[u8]) -> Option<Self> {
+        match compatible {
+            b"ns16550a" | b"ns16550" => Some(Self::Ns16550),
+            b"sifive,uart0" => Some(Self::SifiveUart),
+            b"ucb,htif0" => Some(Self::Htif),
+            _ => None,
+        }
+    }
+}
+
+/// 平台级中断控制器。
+pub(crate) struct PlicInfo {
+    pub reg: Range<usize>,
+    /// 中断源的数量，中断源编号从 1 开始。
+    pub ndev: u32,
+    /// 每个硬件线程的 M 态和 S 态上下文。
+    pub contexts: ListInline<PlicContexts, NUM_HART_MAX>,
+}
+
+/// 一个硬件线程在中断控制器上的上下文编号。
+#[derive(Clone, Default)]
+pub(crate) struct PlicContexts {
+    pub hart: u32,
+    pub machine: Option<u16>,
+    pub supervisor: Option<u16>,
+}
+
+/// 遍历设备树时正在访问的节点中与控制台有关的属性。
+#[derive(Default)]
+struct ConsoleNode {
+    path: StringInline<64>,
+    kind: Option<ConsoleKind>,
+    reg: Range<usize>,
+    irq: Option<u32>,
+    clock_frequency: Option<u32>,
+    current_speed: Option<u32>,
+    reg_shift: Option<u32>,
+    reg_io_width: Option<u32>,
+}
+
+/// `/aliases` 中的一项。
+#[derive(Default)]
+struct Alias {
+    name: StringInline<32>,
+    path: StringInline<64>,
 }
 
 /// 在栈上存储有限长度字符串。
+#[derive(Clone)]
 pub(crate) struct StringInline<const N: usize>(usize, [u8; N]);
 
+impl<const N: usize> Default for StringInline<N> {
+    #[inline]
+    fn default() -> Self {
+        Self(0, [0; N])
+    }
+}
+
+impl<const N: usize> StringInline<N> {
+    /// 复制字符串，超出长度上限的部分被截断。
+    #[inline]
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let len = bytes.len().min(N);
+        let mut buf = [0; N];
+        buf[..len].copy_from_slice(&bytes[..len]);
+        Self(len, buf)
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.1[..self.0]
+    }
+}
+
 impl<const N: usize> Display for StringInline<N> {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "{}", unsafe {
@@ -25,26 +147,110 @@ impl<const N: usize> Display for StringInline<N> {
     }
 }
 
+/// 在栈上存储有限数量的元素。
+#[derive(Clone)]
+pub(crate) struct ListInline<T, const N: usize>(usize, [T; N]);
+
+/// 在栈上存储有限数量的地址范围。
+pub(crate) type RangeList<const N: usize> = ListInline<Range<usize>, N>;
+
+impl<T: Default, const N: usize> ListInline<T, N> {
+    #[inline]
+    pub fn new() -> Self {
+        Self(0, core::array::from_fn(|_| T::default()))
+    }
+
+    /// 添加一个元素，已满时返回 `false`。
+    #[inline]
+    pub fn push(&mut self, item: T) -> bool {
+        if self.0 < N {
+            self.1[self.0] = item;
+            self.0 += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<const N: usize> RangeList<N> {
+    /// 判断 `start..end` 是否完整落在某一个地址范围内。
+    #[inline]
+    pub fn contains_range(&self, start: usize, end: usize) -> bool {
+        start < end && self.iter().any(|r| r.start <= start && end <= r.end)
+    }
+}
+
+impl<T, const N: usize> Deref for ListInline<T, N> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.1[..self.0]
+    }
+}
+
+impl<T, const N: usize> DerefMut for ListInline<T, N> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.1[..self.0]
+    }
+}
+
 /// 解析设备树。
 pub(crate) fn parse(opaque: usize) -> BoardInfo {
     use dtb_walker::{Dtb, DtbObj, HeaderError as E, Property, Str, WalkOperation::*};
     const CPUS: &str = "cpus";
+    const CPU: &str = "cpu@";
     const MEMORY: &str = "memory";
     const SOC: &str = "soc";
-    const UART: &str = "uart";
-    const SERIAL: &str = "serial";
     const TEST: &str = "test";
     const CLINT: &str = "clint";
+    const INTC: &str = "interrupt-controller";
+    const INTERRUPTS: &str = "interrupts";
+    const STDOUT_PATH: &str = "stdout-path";
+    const LINUX_STDOUT_PATH: &str = "linux,stdout-path";
+    const ALIASES: &str = "aliases";
+    const CLOCK_FREQUENCY: &str = "clock-frequency";
+    const CURRENT_SPEED: &str = "current-speed";
+    const REG_SHIFT: &str = "reg-shift";
+    const REG_IO_WIDTH: &str = "reg-io-width";
+    const CHOSEN: &str = "chosen";
+    const NEXT_ADDR: &str = "rustsbi,next-addr";
+    const INITRD_START: &str = "linux,initrd-start";
+    const INITRD_END: &str = "linux,initrd-end";
+    const TIMEBASE: &str = "timebase-frequency";
 
     let mut ans = BoardInfo {
         dtb: opaque..opaque,
         model: StringInline(0, [0u8; 128]),
         smp: 0,
-        mem: 0..0,
-        uart: 0..0,
+        timebase: 10_000_000,
+        harts: ListInline::new(),
+        mem: RangeList::new(),
+        console: None,
+        plic: None,
         test: 0..0,
         clint: 0..0,
+        sstc: false,
+        svadu: false,
+        ssnpm: false,
+        next_addr: None,
+        initrd: None,
     };
+    // 分别按 `riscv,isa` 和 `riscv,isa-extensions` 统计支持这些扩展的硬件线程
+    const EXTENSIONS: [&str; 3] = ["sstc", "svadu", "ssnpm"];
+    let mut count_isa = [0; EXTENSIONS.len()];
+    let mut count_extensions = [0; EXTENSIONS.len()];
+    // 按 `compatible` 识别的控制台设备，遍历到下一个节点时保存当前节点
+    let mut consoles = ListInline::<ConsoleNode, 4>::new();
+    let mut node = ConsoleNode::default();
+    let mut stdout_path = StringInline::<80>::default();
+    let mut aliases = ListInline::<Alias, 8>::new();
+    // 平台级中断控制器的路径，以及每个硬件线程的本地中断控制器的 phandle
+    let mut plic_path = StringInline::<64>::default();
+    let mut intc = ListInline::<[u32; 2], NUM_HART_MAX>::new();
+    let mut cpu = 0;
     let dtb = unsafe {
         Dtb::from_raw_parts_filtered(opaque as _, |e| {
             matches!(e, E::Misaligned(4) | E::LastCompVersion(_))
@@ -52,57 +258,293 @@ pub(crate) fn parse(opaque: usize) -> BoardInfo {
     }
     .unwrap();
     ans.dtb.end += dtb.total_size();
-    dtb.walk(|ctx, obj| match obj {
-        DtbObj::SubNode { name } => {
-            let current = ctx.name();
-            if ctx.is_root() {
-                if name == Str::from(CPUS) || name == Str::from(SOC) || name.starts_with(MEMORY) {
+    dtb.walk(|ctx, obj| {
+        if let DtbObj::Property(_) = obj {
+            let mut path = StringInline::<64>::default();
+            path.0 = ctx.fmt_path(&mut path.1).unwrap_or_else(|len| len);
+            if path.as_bytes() != node.path.as_bytes() {
+                let last = core::mem::replace(
+                    &mut node,
+                    ConsoleNode {
+                        path,
+                        ..Default::default()
+                    },
+                );
+                if last.kind.is_some() {
+                    consoles.push(last);
+                }
+            }
+        }
+        match obj {
+            DtbObj::SubNode { name } => {
+                let current = ctx.name();
+                // 控制台设备可能位于根节点或 `soc` 下的任何节点
+                if ctx.is_root() || current == Str::from(SOC) {
+                    StepInto
+                } else if current == Str::from(CPUS) && name.starts_with(CPU) {
+                    ans.smp += 1;
+                    StepInto
+                } else if current.starts_with(CPU) && name == Str::from(INTC) {
                     StepInto
                 } else {
                     StepOver
                 }
-            } else if current == Str::from(SOC) {
-                if name.starts_with(UART)
-                    || name.starts_with(SERIAL)
-                    || name.starts_with(TEST)
-                    || name.starts_with(CLINT)
-                {
-                    StepInto
+            }
+            DtbObj::Property(Property::Model(model)) if ctx.is_root() => {
+                ans.model.0 = model.as_bytes().len();
+                ans.model.1[..ans.model.0].copy_from_slice(model.as_bytes());
+                StepOver
+            }
+            DtbObj::Property(Property::Reg(mut reg)) => {
+                node.reg = reg.clone().next().unwrap_or(0..0);
+                let node = ctx.name();
+                if node.starts_with(TEST) {
+                    ans.test = reg.next().unwrap();
+                    StepOut
+                } else if node.starts_with(CLINT) {
+                    ans.clint = reg.next().unwrap();
+                    StepOut
+                } else if node.starts_with(CPU) {
+                    cpu = reg.next().unwrap().start as _;
+                    ans.harts.push(cpu);
+                    StepOver
+                } else if node.starts_with(MEMORY) {
+                    // 超出数量上限的区域不会交给特权软件使用
+                    for region in reg.filter(|r| !r.is_empty()) {
+                        ans.mem.push(region);
+                    }
+                    StepOut
                 } else {
                     StepOver
                 }
-            } else {
-                if current == Str::from(CPUS) && name.starts_with("cpu@") {
-                    ans.smp += 1;
+            }
+            DtbObj::Property(Property::General { name, value }) if ctx.name().starts_with(CPU) => {
+                for (i, ext) in EXTENSIONS.iter().enumerate() {
+                    if name == Str::from("riscv,isa") && isa_has_extension(value, ext) {
+                        count_isa[i] += 1;
+                    } else if name == Str::from("riscv,isa-extensions")
+                        && value.split(|c| *c == b'\0').any(|e| e == ext.as_bytes())
+                    {
+                        count_extensions[i] += 1;
+                    }
                 }
                 StepOver
             }
-        }
-        DtbObj::Property(Property::Model(model)) if ctx.is_root() => {
-            ans.model.0 = model.as_bytes().len();
-            ans.model.1[..ans.model.0].copy_from_slice(model.as_bytes());
-            StepOver
-        }
-        DtbObj::Property(Property::Reg(mut reg)) => {
-            let node = ctx.name();
-            if node.starts_with(UART) || node.starts_with(SERIAL) {
-                ans.uart = reg.next().unwrap();
-                StepOut
-            } else if node.starts_with(TEST) {
-                ans.test = reg.next().unwrap();
-                StepOut
-            } else if node.starts_with(CLINT) {
-                ans.clint = reg.next().unwrap();
-                StepOut
-            } else if node.starts_with(MEMORY) {
-                ans.mem = reg.next().unwrap();
-                StepOut
-            } else {
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(CPUS) && name == Str::from(TIMEBASE) =>
+            {
+                match value.len() {
+                    4 => ans.timebase = u32::from_be_bytes(value.try_into().unwrap()) as _,
+                    8 => ans.timebase = u64::from_be_bytes(value.try_into().unwrap()),
+                    _ => {}
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(CHOSEN) && name == Str::from(NEXT_ADDR) =>
+            {
+                // 1 个或 2 个大端 32 位数
+                ans.next_addr = match value.len() {
+                    4 => Some(u32::from_be_bytes(value.try_into().unwrap()) as _),
+                    8 => Some(u64::from_be_bytes(value.try_into().unwrap()) as _),
+                    _ => None,
+                };
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(CHOSEN)
+                    && (name == Str::from(INITRD_START) || name == Str::from(INITRD_END)) =>
+            {
+                let addr = match value.len() {
+                    4 => u32::from_be_bytes(value.try_into().unwrap()) as usize,
+                    8 => u64::from_be_bytes(value.try_into().unwrap()) as usize,
+                    _ => return StepOver,
+                };
+                let initrd = ans.initrd.get_or_insert(0..0);
+                if name == Str::from(INITRD_START) {
+                    initrd.start = addr;
+                } else {
+                    initrd.end = addr;
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::PHandle(phandle)) if ctx.name() == Str::from(INTC) => {
+                intc.push([phandle.value(), cpu]);
+                StepOver
+            }
+            DtbObj::Property(Property::Compatible(compatible)) => {
+                for c in compatible {
+                    if matches!(c.as_bytes(), b"riscv,plic0" | b"sifive,plic-1.0.0") {
+                        plic_path = node.path.clone();
+                    }
+                    node.kind = node.kind.or(ConsoleKind::from_compatible(c.as_bytes()));
+                }
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if name == Str::from(INTERRUPTS) =>
+            {
+                // 中断控制器的 `#interrupt-cells` 为 1
+                node.irq = read_cell(value);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(CHOSEN)
+                    && (name == Str::from(STDOUT_PATH)
+                        || (name == Str::from(LINUX_STDOUT_PATH) && stdout_path.0 == 0)) =>
+            {
+                stdout_path = StringInline::from_bytes(value.strip_suffix(b"\0").unwrap_or(value));
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if ctx.name() == Str::from(ALIASES) =>
+            {
+                aliases.push(Alias {
+                    name: StringInline::from_bytes(name.as_bytes()),
+                    path: StringInline::from_bytes(value.strip_suffix(b"\0").unwrap_or(value)),
+                });
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if name == Str::from(CLOCK_FREQUENCY) =>
+            {
+                node.clock_frequency = read_cell(value).filter(|f| *f != 0);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if name == Str::from(CURRENT_SPEED) =>
+            {
+                node.current_speed = read_cell(value).filter(|s| *s != 0);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value }) if name == Str::from(REG_SHIFT) => {
+                node.reg_shift = read_cell(value);
                 StepOver
             }
+            DtbObj::Property(Property::General { name, value })
+                if name == Str::from(REG_IO_WIDTH) =>
+            {
+                node.reg_io_width = read_cell(value);
+                StepOver
+            }
+            DtbObj::Property(_) => StepOver,
         }
-        DtbObj::Property(_) => StepOver,
     });
+    if node.kind.is_some() {
+        consoles.push(node);
+    }
+    // 优先选择 `/chosen/stdout-path` 指定的设备，它可以是别名，冒号后是串口参数
+    let stdout_path = stdout_path.as_bytes();
+    let (path, options) = match stdout_path.iter().position(|c| *c == b':') {
+        Some(i) => (&stdout_path[..i], &stdout_path[i + 1..]),
+        None => (stdout_path, &[][..]),
+    };
+    let path = match aliases.iter().find(|a| a.name.as_bytes() == path) {
+        Some(alias) if !path.starts_with(b"/") => alias.path.as_bytes(),
+        _ => path,
+    };
+    let chosen = consoles.iter().find(|c| c.path.as_bytes() == path);
+    ans.console = chosen.or(consoles.first()).map(|c| ConsoleInfo {
+        kind: c.kind.unwrap(),
+        path: c.path.clone(),
+        reg: c.reg.clone(),
+        irq: c.irq,
+        clock_frequency: c.clock_frequency,
+        current_speed: c.current_speed,
+        reg_shift: c.reg_shift.unwrap_or(0),
+        reg_io_width: c.reg_io_width.unwrap_or(1),
+        options: StringInline::from_bytes(if chosen.is_some() { options } else { &[] }),
+    });
+    if plic_path.0 != 0 {
+        ans.plic = Some(parse_plic(&dtb, plic_path.as_bytes(), &intc));
+    }
+    let all_harts =
+        |i: usize| ans.smp > 0 && (count_isa[i] == ans.smp || count_extensions[i] == ans.smp);
+    [ans.sstc, ans.svadu, ans.ssnpm] = [all_harts(0), all_harts(1), all_harts(2)];
+    ans.mem.sort_unstable_by_key(|r| r.start);
 
     ans
 }
+
+/// 读取路径为 `path` 的平台级中断控制器。
+///
+/// `intc` 是每个硬件线程的本地中断控制器的 phandle 和硬件线程编号，
+/// 本地中断控制器的 `#interrupt-cells` 为 1。
+fn parse_plic(dtb: &dtb_walker::Dtb, path: &[u8], intc: &[[u32; 2]]) -> PlicInfo {
+    use dtb_walker::{DtbObj, Property, Str, WalkOperation::*};
+    const SOC: &str = "soc";
+    const NDEV: &str = "riscv,ndev";
+    const INTERRUPTS_EXTENDED: &str = "interrupts-extended";
+    /// 本地中断控制器上的 S 态和 M 态外部中断。
+    const IRQ_S_EXT: u32 = 9;
+    const IRQ_M_EXT: u32 = 11;
+
+    let mut ans = PlicInfo {
+        reg: 0..0,
+        ndev: 0,
+        contexts: ListInline::new(),
+    };
+    dtb.walk(|ctx, obj| {
+        let mut buf = [0u8; 64];
+        let len = ctx.fmt_path(&mut buf).unwrap_or_else(|len| len);
+        match obj {
+            DtbObj::SubNode { .. } if ctx.is_root() || ctx.name() == Str::from(SOC) => StepInto,
+            DtbObj::SubNode { .. } => StepOver,
+            _ if &buf[..len] != path => StepOver,
+            DtbObj::Property(Property::Reg(mut reg)) => {
+                ans.reg = reg.next().unwrap_or(0..0);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value }) if name == Str::from(NDEV) => {
+                ans.ndev = read_cell(value).unwrap_or(0);
+                StepOver
+            }
+            DtbObj::Property(Property::General { name, value })
+                if name == Str::from(INTERRUPTS_EXTENDED) =>
+            {
+                // 第 i 项对应上下文 i
+                for (i, item) in value.chunks_exact(8).enumerate() {
+                    let phandle = read_cell(&item[..4]).unwrap();
+                    let irq = read_cell(&item[4..]).unwrap();
+                    let Some(&[_, hart]) = intc.iter().find(|[p, _]| *p == phandle) else {
+                        continue;
+                    };
+                    let contexts = match ans.contexts.iter().position(|c| c.hart == hart) {
+                        Some(j) => &mut ans.contexts[j],
+                        None if ans.contexts.push(PlicContexts {
+                            hart,
+                            ..Default::default()
+                        }) =>
+                        {
+                            ans.contexts.last_mut().unwrap()
+                        }
+                        None => continue,
+                    };
+                    match irq {
+                        IRQ_M_EXT => contexts.machine = Some(i as _),
+                        IRQ_S_EXT => contexts.supervisor = Some(i as _),
+                        _ => {}
+                    }
+                }
+                StepOver
+            }
+            DtbObj::Property(_) => StepOver,
+        }
+    });
+    ans
+}
+
+/// 读取 1 个大端 32 位数。
+fn read_cell(value: &[u8]) -> Option<u32> {
+    value
+        .get(..4)
+        .map(|cell| u32::from_be_bytes(cell.try_into().unwrap()))
+}
+
+/// 检查 `riscv,isa` 字符串中是否包含多字母扩展 `ext`。
+fn isa_has_extension(isa: &[u8], ext: &str) -> bool {
+    let isa = isa.strip_suffix(b"\0").unwrap_or(isa);
+    isa.split(|c| *c == b'_')
+        .skip(1)
+        .any(|e| e.eq_ignore_ascii_case(ext.as_bytes()))
+}
@@ -0,0 +1,403 @@
+use crate::{device_tree::BoardInfo, payload, RESERVE_CONSOLE};
+use core::{
+    fmt::{self, Write},
+    ops::Range,
+};
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// 头部长度，同时是新设备树中内存保留表的偏移。
+const LEN_HEADER: usize = 40;
+
+const RESERVED_MEMORY: &[u8] = b"reserved-memory";
+const MMODE_RESV: &[u8] = b"mmode_resv@";
+const ADDRESS_CELLS: &[u8] = b"#address-cells";
+const SIZE_CELLS: &[u8] = b"#size-cells";
+const RANGES: &[u8] = b"ranges";
+const REG: &[u8] = b"reg";
+const NO_MAP: &[u8] = b"no-map";
+const STATUS: &[u8] = b"status";
+const RESERVED: &[u8] = b"reserved\0";
+
+/// 向设备树添加 `/reserved-memory/mmode_resv@...` 节点，以 `no-map` 保留 `firmware` 区域。
+///
+/// 构建时打开 [`RESERVE_CONSOLE`] 时，SBI 使用的控制台设备节点的 `status` 改为 `reserved`，
+/// 特权软件应通过 DBCN 使用控制台。
+///
+/// 设备树需要扩容，修改后的副本放在原设备树之前紧邻的位置，并在内存保留表中保留副本自身，
+/// 热重启时还要从副本读取设备树。
+/// 返回应交给特权软件的设备树区域；节点已存在或无法修改时返回原设备树区域。
+pub(crate) fn reserve_firmware(board_info: &BoardInfo, firmware: Range<usize>) -> Range<usize> {
+    let dtb = board_info.dtb.clone();
+    let src = unsafe { core::slice::from_raw_parts(dtb.start as *const u8, dtb.len()) };
+    let floor = firmware.end;
+    let console = board_info
+        .console
+        .as_ref()
+        .filter(|_| RESERVE_CONSOLE)
+        .map(|c| c.path.as_bytes());
+    patch(src, firmware, console, |len| {
+        // 副本和原设备树位于同一个内存区域，且不能覆盖 SBI、内嵌的特权软件和初始内存盘
+        let bank = board_info.mem.iter().find(|r| r.contains(&dtb.start))?;
+        let start = dtb.start.checked_sub(len)? & !7;
+        let overlaps = |r: &Range<usize>| r.start < dtb.start && start < r.end;
+        (start >= bank.start.max(floor)
+            && !overlaps(&payload::range())
+            && !board_info.initrd.as_ref().is_some_and(overlaps))
+        .then_some(start)
+    })
+    .unwrap_or(dtb)
+}
+
+/// 扫描设备树结构块得到的信息。
+struct Scan {
+    /// 根节点 `FDT_END_NODE` 的偏移。
+    root_end: usize,
+    /// `/reserved-memory` 节点 `FDT_END_NODE` 的偏移。
+    resv_end: Option<usize>,
+    /// 根节点的 `#address-cells` 和 `#size-cells`。
+    root_cells: (u32, u32),
+    /// `/reserved-memory` 节点的 `#address-cells` 和 `#size-cells`。
+    resv_cells: (u32, u32),
+    /// 控制台节点第一个属性的偏移。
+    console_props: Option<usize>,
+    /// 控制台节点原有的 `status` 属性。
+    console_status: Option<Range<usize>>,
+}
+
+/// 生成添加了保留节点、保留了控制台节点的设备树。
+///
+/// `place` 根据新设备树的长度决定其地址。
+fn patch(
+    src: &[u8],
+    firmware: Range<usize>,
+    console: Option<&[u8]>,
+    place: impl FnOnce(usize) -> Option<usize>,
+) -> Option<Range<usize>> {
+    if be32(src, 0)? != FDT_MAGIC || be32(src, 20)? < 17 {
+        return None;
+    }
+    let off_struct = be32(src, 8)? as usize;
+    let off_strings = be32(src, 12)? as usize;
+    let off_rsvmap = be32(src, 16)? as usize;
+    let boot_cpuid = be32(src, 28)?;
+    let size_strings = be32(src, 32)? as usize;
+    let size_struct = be32(src, 36)? as usize;
+    let structs = src.get(off_struct..off_struct + size_struct)?;
+    let strings = src.get(off_strings..off_strings + size_strings)?;
+    let rsvmap = src.get(off_rsvmap..off_rsvmap + rsvmap_len(src.get(off_rsvmap..)?)?)?;
+    let scan = scan(structs, strings, console)?;
+
+    // 查找或追加属性名
+    let mut appended = [&[][..]; 6];
+    let mut num_appended = 0;
+    let mut len_appended = 0;
+    let mut name_off = |name: &'static [u8]| -> u32 {
+        find_string(strings, name).unwrap_or_else(|| {
+            appended[num_appended] = name;
+            num_appended += 1;
+            len_appended += name.len() + 1;
+            size_strings + len_appended - name.len() - 1
+        }) as u32
+    };
+
+    // 生成插入结构块的节点
+    let mut node = [0u8; 256];
+    let mut w = Writer::new(&mut node);
+    let (address_cells, size_cells) = match scan.resv_end {
+        Some(_) => scan.resv_cells,
+        None => {
+            w.begin_node(format_args!("{}", Bytes(RESERVED_MEMORY)))?;
+            w.prop(name_off(ADDRESS_CELLS), &scan.root_cells.0.to_be_bytes())?;
+            w.prop(name_off(SIZE_CELLS), &scan.root_cells.1.to_be_bytes())?;
+            w.prop(name_off(RANGES), &[])?;
+            scan.root_cells
+        }
+    };
+    let mut reg = [0u8; 16];
+    let mut r = Writer::new(&mut reg);
+    r.cells(address_cells, firmware.start)?;
+    r.cells(size_cells, firmware.len())?;
+    let len_reg = r.pos;
+    w.begin_node(format_args!("{}{:x}", Bytes(MMODE_RESV), firmware.start))?;
+    w.prop(name_off(REG), &reg[..len_reg])?;
+    w.prop(name_off(NO_MAP), &[])?;
+    w.u32(FDT_END_NODE)?;
+    if scan.resv_end.is_none() {
+        w.u32(FDT_END_NODE)?;
+    }
+    let len_node = w.pos;
+    let node = &node[..len_node];
+    let insert = scan.resv_end.unwrap_or(scan.root_end);
+
+    // 替换控制台节点的 `status` 属性
+    let mut status = [0u8; 24];
+    let mut w = Writer::new(&mut status);
+    if scan.console_props.is_some() {
+        w.prop(name_off(STATUS), RESERVED)?;
+    }
+    let len_status = w.pos;
+    let status = &status[..len_status];
+    let old_status = scan.console_status.clone().unwrap_or(0..0);
+    // 按偏移排列的修改：在偏移处删除一段并插入一段
+    let mut edits = [
+        (insert, 0, node),
+        (scan.console_props.unwrap_or(0), 0, status),
+        (old_status.start, old_status.len(), &[][..]),
+    ];
+    // 同一偏移先插入再删除
+    edits.sort_unstable_by_key(|(pos, skip, _)| (*pos, *skip));
+
+    // 计算新设备树布局
+    let off_rsvmap = LEN_HEADER;
+    // 内存保留表增加一项
+    let off_struct = off_rsvmap + rsvmap.len() + 16;
+    let size_struct = size_struct + node.len() + status.len() - old_status.len();
+    let off_strings = off_struct + size_struct;
+    let size_strings = size_strings + len_appended;
+    let total = (off_strings + size_strings + 7) & !7;
+    let start = place(total)?;
+
+    let dst = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, total) };
+    let mut w = Writer::new(dst);
+    for field in [
+        FDT_MAGIC,
+        total as u32,
+        off_struct as u32,
+        off_strings as u32,
+        off_rsvmap as u32,
+        17,
+        16,
+        boot_cpuid,
+        size_strings as u32,
+        size_struct as u32,
+    ] {
+        w.u32(field)?;
+    }
+    w.bytes(&rsvmap[..rsvmap.len() - 16])?;
+    w.bytes(&(start as u64).to_be_bytes())?;
+    w.bytes(&(total as u64).to_be_bytes())?;
+    w.bytes(&[0; 16])?;
+    let mut pos = 0;
+    for (at, skip, bytes) in edits {
+        w.bytes(&structs[pos..at])?;
+        w.bytes(bytes)?;
+        pos = at + skip;
+    }
+    w.bytes(&structs[pos..])?;
+    w.bytes(strings)?;
+    for name in &appended[..num_appended] {
+        w.bytes(name)?;
+        w.bytes(&[0])?;
+    }
+    w.align()?;
+    Some(start..start + total)
+}
+
+/// 扫描结构块，找到插入位置和路径为 `console` 的节点。已存在保留节点时返回 `None`。
+fn scan(structs: &[u8], strings: &[u8], console: Option<&[u8]>) -> Option<Scan> {
+    let mut ans = Scan {
+        root_end: 0,
+        resv_end: None,
+        root_cells: (2, 1),
+        resv_cells: (2, 1),
+        console_props: None,
+        console_status: None,
+    };
+    // 控制台节点路径除根节点外的每一级名字，以及当前节点的祖先中已匹配的级数
+    let mut components = [&[][..]; 8];
+    let mut num_components = 0;
+    for name in console
+        .into_iter()
+        .flat_map(|p| p.split(|c| *c == b'/').skip(1))
+    {
+        // 层数过多时不修改控制台节点
+        let Some(component) = components.get_mut(num_components) else {
+            num_components = 0;
+            break;
+        };
+        *component = name;
+        num_components += 1;
+    }
+    let components = &components[..num_components];
+    let mut matched = 0;
+    let mut pos = 0;
+    let mut depth = 0usize;
+    let mut in_resv = false;
+    loop {
+        let token = be32(structs, pos)?;
+        pos += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(structs.get(pos..)?)?;
+                pos += align4(name.len() + 1);
+                depth += 1;
+                if depth >= 2 && matched == depth - 2 && components.get(matched) == Some(&name) {
+                    matched += 1;
+                    if matched == components.len() {
+                        ans.console_props = Some(pos);
+                    }
+                }
+                if depth == 2 {
+                    in_resv = name == RESERVED_MEMORY;
+                } else if depth == 3 && in_resv && name.starts_with(MMODE_RESV) {
+                    return None;
+                }
+            }
+            FDT_END_NODE => {
+                matched = matched.min(depth.saturating_sub(2));
+                match depth {
+                    1 => ans.root_end = pos - 4,
+                    2 if in_resv => ans.resv_end = Some(pos - 4),
+                    _ => {}
+                }
+                depth = depth.checked_sub(1)?;
+            }
+            FDT_PROP => {
+                let len = be32(structs, pos)? as usize;
+                let name = cstr(strings.get(be32(structs, pos + 4)? as usize..)?)?;
+                let value = structs.get(pos + 8..pos + 8 + len)?;
+                let start = pos - 4;
+                pos += 8 + align4(len);
+                if name == STATUS
+                    && !components.is_empty()
+                    && matched == components.len()
+                    && depth == matched + 1
+                {
+                    ans.console_status = Some(start..pos);
+                }
+                let cells = match depth {
+                    1 => &mut ans.root_cells,
+                    2 if in_resv => &mut ans.resv_cells,
+                    _ => continue,
+                };
+                if name == ADDRESS_CELLS {
+                    cells.0 = be32(value, 0)?;
+                } else if name == SIZE_CELLS {
+                    cells.1 = be32(value, 0)?;
+                }
+            }
+            FDT_NOP => {}
+            FDT_END if depth == 0 => break Some(ans),
+            _ => return None,
+        }
+    }
+}
+
+/// 内存保留表的长度，包括结尾的空表项。
+fn rsvmap_len(rsvmap: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let entry = rsvmap.get(pos..pos + 16)?;
+        pos += 16;
+        if entry.iter().all(|b| *b == 0) {
+            break Some(pos);
+        }
+    }
+}
+
+/// 在字符串块中查找 `name`，返回偏移。
+fn find_string(strings: &[u8], name: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while pos < strings.len() {
+        let s = cstr(&strings[pos..])?;
+        if s == name {
+            return Some(pos);
+        }
+        pos += s.len() + 1;
+    }
+    None
+}
+
+#[inline]
+fn be32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[inline]
+fn cstr(buf: &[u8]) -> Option<&[u8]> {
+    buf.iter().position(|b| *b == 0).map(|len| &buf[..len])
+}
+
+#[inline]
+const fn align4(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// 以字符串形式格式化字节串。
+struct Bytes(&'static [u8]);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(unsafe { core::str::from_utf8_unchecked(self.0) })
+    }
+}
+
+/// 向缓冲区顺序写入设备树内容，越界时返回 `None`。
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    #[inline]
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.pos + bytes.len();
+        self.buf.get_mut(self.pos..end)?.copy_from_slice(bytes);
+        self.pos = end;
+        Some(())
+    }
+
+    /// 补零到 4 字节对齐。
+    fn align(&mut self) -> Option<()> {
+        while self.pos % 4 != 0 {
+            self.bytes(&[0])?;
+        }
+        Some(())
+    }
+
+    #[inline]
+    fn u32(&mut self, val: u32) -> Option<()> {
+        self.bytes(&val.to_be_bytes())
+    }
+
+    /// 以 `cells` 个大端 32 位数写入 `val`。
+    fn cells(&mut self, cells: u32, val: usize) -> Option<()> {
+        match cells {
+            1 => self.u32(val.try_into().ok()?),
+            2 => self.bytes(&(val as u64).to_be_bytes()),
+            _ => None,
+        }
+    }
+
+    fn begin_node(&mut self, name: fmt::Arguments) -> Option<()> {
+        self.u32(FDT_BEGIN_NODE)?;
+        self.write_fmt(name).ok()?;
+        self.bytes(&[0])?;
+        self.align()
+    }
+
+    fn prop(&mut self, name_off: u32, value: &[u8]) -> Option<()> {
+        self.u32(FDT_PROP)?;
+        self.u32(value.len() as u32)?;
+        self.u32(name_off)?;
+        self.bytes(value)?;
+        self.align()
+    }
+}
+
+impl Write for Writer<'_> {
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.bytes(s.as_bytes()).ok_or(fmt::Error)
+    }
+}
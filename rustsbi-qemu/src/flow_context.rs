@@ -0,0 +1,50 @@
+use core::arch::asm;
+use fast_trap::FlowContext;
+
+/// 按编号读取陷入上下文中的通用寄存器。
+///
+/// 仅在完整路径中使用：此时 sp 位于 `mscratch`，gp 和 tp 仍在寄存器中。
+pub(crate) fn read_reg(ctx: &FlowContext, i: usize) -> usize {
+    let val: usize;
+    match i {
+        0 => 0,
+        1 => ctx.ra,
+        2 => {
+            unsafe { asm!("csrr {}, mscratch", out(reg) val, options(nomem)) };
+            val
+        }
+        3 => {
+            unsafe { asm!("mv {}, gp", out(reg) val, options(nomem)) };
+            val
+        }
+        4 => {
+            unsafe { asm!("mv {}, tp", out(reg) val, options(nomem)) };
+            val
+        }
+        5..=7 => ctx.t[i - 5],
+        8..=9 => ctx.s[i - 8],
+        10..=17 => ctx.a[i - 10],
+        18..=27 => ctx.s[i - 16],
+        28..=31 => ctx.t[i - 25],
+        _ => unreachable!(),
+    }
+}
+
+/// 按编号写入陷入上下文中的通用寄存器。
+///
+/// 仅在完整路径中使用：此时 sp 位于 `mscratch`，gp 和 tp 仍在寄存器中。
+pub(crate) fn write_reg(ctx: &mut FlowContext, i: usize, val: usize) {
+    match i {
+        0 => {}
+        1 => ctx.ra = val,
+        2 => unsafe { asm!("csrw mscratch, {}", in(reg) val, options(nomem)) },
+        3 => unsafe { asm!("mv gp, {}", in(reg) val, options(nomem)) },
+        4 => unsafe { asm!("mv tp, {}", in(reg) val, options(nomem)) },
+        5..=7 => ctx.t[i - 5] = val,
+        8..=9 => ctx.s[i - 8] = val,
+        10..=17 => ctx.a[i - 10] = val,
+        18..=27 => ctx.s[i - 16] = val,
+        28..=31 => ctx.t[i - 25] = val,
+        _ => unreachable!(),
+    }
+}
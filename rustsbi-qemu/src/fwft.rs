@@ -0,0 +1,172 @@
+use crate::{riscv_spec::menvcfg, trap_stack::local_fwft};
+use core::sync::atomic::{AtomicBool, Ordering};
+use riscv::register::medeleg;
+use rustsbi::SbiRet;
+
+/// 固件特性扩展号 "FWFT"。
+///
+/// 特权软件通过它设置非对齐访存异常代理、A/D 位硬件更新和指针掩码等特性，特性的值保存在 CSR 中。
+pub(crate) const EID_FWFT: usize = 0x4657_4654;
+const SET: usize = 0;
+const GET: usize = 1;
+
+/// 标准特性编号。
+mod feature {
+    pub const MISALIGNED_EXC_DELEG: u32 = 0;
+    pub const LANDING_PAD: u32 = 1;
+    pub const SHADOW_STACK: u32 = 2;
+    pub const DOUBLE_TRAP: u32 = 3;
+    pub const PTE_AD_HW_UPDATING: u32 = 4;
+    pub const POINTER_MASKING_PMLEN: u32 = 5;
+}
+
+/// 设置后锁定，直到 hart 重新启动。
+const FLAG_LOCK: usize = 1 << 0;
+
+/// SBI v3.0 定义的 `SBI_ERR_DENIED_LOCKED`。
+const ERR_DENIED_LOCKED: usize = -14isize as usize;
+
+/// 所有硬件线程都支持 Svadu 扩展。
+static SVADU: AtomicBool = AtomicBool::new(false);
+/// 所有硬件线程都支持 Ssnpm 扩展。
+static SSNPM: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn init(svadu: bool, ssnpm: bool) {
+    SVADU.store(svadu, Ordering::Relaxed);
+    SSNPM.store(ssnpm, Ordering::Relaxed);
+}
+
+/// 每个 hart 的特性状态。
+///
+/// 特性的值保存在对应的 CSR 中，这里只记录锁定的特性。
+pub(crate) struct FwftState {
+    locked: u32,
+}
+
+impl FwftState {
+    pub const fn new() -> Self {
+        Self { locked: 0 }
+    }
+}
+
+/// 将此 hart 的所有特性恢复为默认值并解锁。
+///
+/// 特权软件启动时调用。非对齐访存默认由 SBI 模拟。
+pub(crate) fn reset() {
+    local_fwft().locked = 0;
+    unsafe {
+        medeleg::clear_load_misaligned();
+        medeleg::clear_store_misaligned();
+    }
+    if SVADU.load(Ordering::Relaxed) {
+        menvcfg::clear(menvcfg::ADUE);
+    }
+    if SSNPM.load(Ordering::Relaxed) {
+        menvcfg::clear(menvcfg::PMM);
+    }
+}
+
+/// 处理固件特性扩展的调用。
+pub(crate) fn handle(fid: usize, [feature, value, flags]: [usize; 3]) -> SbiRet {
+    match fid {
+        SET => set(feature as _, value, flags),
+        GET => get(feature as _),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// 检查特性是否可用。
+///
+/// 保留的和未实现的平台特性返回 `SBI_ERR_DENIED`，不支持的标准特性返回 `SBI_ERR_NOT_SUPPORTED`。
+fn check(feature: u32) -> Result<(), SbiRet> {
+    use feature::*;
+    match feature {
+        MISALIGNED_EXC_DELEG => Ok(()),
+        PTE_AD_HW_UPDATING if SVADU.load(Ordering::Relaxed) => Ok(()),
+        POINTER_MASKING_PMLEN if SSNPM.load(Ordering::Relaxed) => Ok(()),
+        LANDING_PAD | SHADOW_STACK | DOUBLE_TRAP | PTE_AD_HW_UPDATING | POINTER_MASKING_PMLEN => {
+            Err(SbiRet::not_supported())
+        }
+        _ => Err(SbiRet::denied()),
+    }
+}
+
+fn set(feature: u32, value: usize, flags: usize) -> SbiRet {
+    use feature::*;
+    if let Err(e) = check(feature) {
+        return e;
+    }
+    if flags & !FLAG_LOCK != 0 {
+        return SbiRet::invalid_param();
+    }
+    let state = local_fwft();
+    if state.locked & (1 << feature) != 0 {
+        return SbiRet {
+            error: ERR_DENIED_LOCKED,
+            value: 0,
+        };
+    }
+    match (feature, value) {
+        (MISALIGNED_EXC_DELEG, 0) => unsafe {
+            medeleg::clear_load_misaligned();
+            medeleg::clear_store_misaligned();
+        },
+        (MISALIGNED_EXC_DELEG, 1) => unsafe {
+            medeleg::set_load_misaligned();
+            medeleg::set_store_misaligned();
+        },
+        (PTE_AD_HW_UPDATING, 0) => menvcfg::clear(menvcfg::ADUE),
+        (PTE_AD_HW_UPDATING, 1) => menvcfg::set(menvcfg::ADUE),
+        (POINTER_MASKING_PMLEN, pmlen) => {
+            if !set_pmlen(pmlen) {
+                return SbiRet::invalid_param();
+            }
+        }
+        _ => return SbiRet::invalid_param(),
+    }
+    if flags & FLAG_LOCK != 0 {
+        state.locked |= 1 << feature;
+    }
+    SbiRet::success(0)
+}
+
+fn get(feature: u32) -> SbiRet {
+    use feature::*;
+    if let Err(e) = check(feature) {
+        return e;
+    }
+    let value = match feature {
+        MISALIGNED_EXC_DELEG => medeleg::read().load_misaligned() as _,
+        PTE_AD_HW_UPDATING => (menvcfg::read() & menvcfg::ADUE != 0) as _,
+        POINTER_MASKING_PMLEN => match menvcfg::read() & menvcfg::PMM {
+            menvcfg::PMM_PMLEN_7 => 7,
+            menvcfg::PMM_PMLEN_16 => 16,
+            _ => 0,
+        },
+        _ => unreachable!(),
+    };
+    SbiRet::success(value)
+}
+
+/// 选择不小于 `pmlen` 的最小的已实现的指针掩码长度，没有时返回 `false` 并保持原设置。
+fn set_pmlen(pmlen: usize) -> bool {
+    if pmlen == 0 {
+        menvcfg::clear(menvcfg::PMM);
+        return true;
+    }
+    let old = menvcfg::read() & menvcfg::PMM;
+    for (len, pmm) in [(7, menvcfg::PMM_PMLEN_7), (16, menvcfg::PMM_PMLEN_16)] {
+        if len < pmlen {
+            continue;
+        }
+        // PMM 是 WARL 的，读回确认
+        menvcfg::clear(menvcfg::PMM);
+        menvcfg::set(pmm);
+        if menvcfg::read() & menvcfg::PMM == pmm {
+            return true;
+        }
+    }
+    menvcfg::clear(menvcfg::PMM);
+    menvcfg::set(old);
+    false
+}
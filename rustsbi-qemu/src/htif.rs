@@ -0,0 +1,85 @@
+use crate::console::ConsoleDevice;
+use core::{
+    ptr::{addr_of, addr_of_mut},
+    sync::atomic::{AtomicU16, Ordering},
+};
+
+/// 主机通过这两个符号与固件通信，需要保留在 ELF 符号表中。
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut tohost: u64 = 0;
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut fromhost: u64 = 0;
+
+/// 控制台设备号和命令。
+const DEV_CONSOLE: u64 = 1;
+const CMD_GETC: u64 = 0;
+const CMD_PUTC: u64 = 1;
+
+#[inline]
+const fn command(dev: u64, cmd: u64, data: u64) -> u64 {
+    dev << 56 | cmd << 48 | data
+}
+
+static INSTANCE: Htif = Htif {
+    received: AtomicU16::new(0),
+};
+
+pub(crate) fn init() -> &'static dyn ConsoleDevice {
+    // 预先请求一个输入字符，主机在有输入时回复
+    unsafe { addr_of_mut!(tohost).write_volatile(command(DEV_CONSOLE, CMD_GETC, 0)) };
+    &INSTANCE
+}
+
+/// 宿主机目标接口（HTIF）控制台。
+pub(crate) struct Htif {
+    /// 主机回复的输入字符，第 8 位表示有效。
+    received: AtomicU16,
+}
+
+impl Htif {
+    const VALID: u16 = 1 << 8;
+
+    /// 处理主机的回复。
+    fn check_fromhost(&self) {
+        let reply = unsafe { addr_of!(fromhost).read_volatile() };
+        if reply == 0 {
+            return;
+        }
+        unsafe { addr_of_mut!(fromhost).write_volatile(0) };
+        if reply >> 48 == command(DEV_CONSOLE, CMD_GETC, 0) >> 48 {
+            self.received
+                .store(Self::VALID | reply as u8 as u16, Ordering::Relaxed);
+        }
+    }
+
+    /// 主机是否已取走上一个命令。
+    #[inline]
+    fn idle(&self) -> bool {
+        self.check_fromhost();
+        unsafe { addr_of!(tohost).read_volatile() == 0 }
+    }
+}
+
+impl ConsoleDevice for Htif {
+    fn receive(&self) -> Option<u8> {
+        self.check_fromhost();
+        let received = self.received.load(Ordering::Relaxed);
+        if received & Self::VALID == 0 || !self.idle() {
+            return None;
+        }
+        self.received.store(0, Ordering::Relaxed);
+        // 请求下一个输入字符
+        unsafe { addr_of_mut!(tohost).write_volatile(command(DEV_CONSOLE, CMD_GETC, 0)) };
+        Some(received as u8)
+    }
+
+    fn transmit(&self, c: u8) -> bool {
+        if !self.idle() {
+            return false;
+        }
+        unsafe { addr_of_mut!(tohost).write_volatile(command(DEV_CONSOLE, CMD_PUTC, c as _)) };
+        true
+    }
+}
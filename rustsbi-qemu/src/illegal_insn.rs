@@ -0,0 +1,65 @@
+use crate::{clint, flow_context::write_reg, pmu, redirect, riscv_spec::mepc, unprivileged};
+use fast_trap::{EntireContext, EntireResult, FlowContext};
+use riscv::register::{mcause, mtval};
+use sbi_spec::pmu::firmware_event;
+
+/// `time` 寄存器的编号。
+const CSR_TIME: u32 = 0xc01;
+/// `timeh` 寄存器的编号。
+#[cfg(target_pointer_width = "32")]
+const CSR_TIMEH: u32 = 0xc81;
+
+/// 模拟非法指令的完整路径。
+///
+/// 硬件未实现 `time` 寄存器时，用 CLINT 的 `mtime` 模拟对它的读取。
+/// 无法识别的指令转发给特权软件。
+pub(crate) extern "C" fn emulate(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    if let Err(trap) = emulate_insn(ctx.regs()) {
+        redirect::redirect(trap);
+    }
+    ctx.restore()
+}
+
+fn emulate_insn(regs: &mut FlowContext) -> Result<(), unprivileged::TrapInfo> {
+    let epc = mepc::read();
+    // 硬件可能不在 mtval 中提供指令
+    let insn = match mtval::read() {
+        0 => unprivileged::load_insn(epc)?,
+        insn => insn as u32,
+    };
+    let unsupported = unprivileged::TrapInfo {
+        cause: mcause::read().bits(),
+        tval: insn as _,
+    };
+    let (rd, csr) = decode_csr_read(insn).ok_or(unsupported)?;
+    let time = clint::read_mtime();
+    let val = match csr {
+        CSR_TIME => time as usize,
+        #[cfg(target_pointer_width = "32")]
+        CSR_TIMEH => (time >> 32) as usize,
+        _ => return Err(unsupported),
+    };
+    write_reg(regs, rd, val);
+    pmu::record(firmware_event::ILLEGAL_INSN);
+    mepc::write(epc + 4);
+    Ok(())
+}
+
+/// 解码只读的 CSR 指令，返回目标寄存器和 CSR 编号。
+///
+/// 源操作数为 0 的 `csrrs`、`csrrc`、`csrrsi` 和 `csrrci` 不写 CSR，只读取 CSR。
+fn decode_csr_read(insn: u32) -> Option<(usize, u32)> {
+    const OPCODE_SYSTEM: u32 = 0b111_0011;
+    if insn & 0x7f != OPCODE_SYSTEM {
+        return None;
+    }
+    let rd = ((insn >> 7) & 0x1f) as usize;
+    let rs1 = (insn >> 15) & 0x1f;
+    let csr = insn >> 20;
+    match (insn >> 12) & 0b111 {
+        // csrrs, csrrc, csrrsi, csrrci
+        0b010 | 0b011 | 0b110 | 0b111 if rs1 == 0 => Some((rd, csr)),
+        _ => None,
+    }
+}
@@ -4,39 +4,94 @@
 #![deny(warnings)]
 
 mod clint;
+mod console;
+mod cppc;
 mod dbcn;
+mod dbtr;
 mod device_tree;
+mod fdt_patch;
+mod flow_context;
+mod fwft;
 mod hart_csr_utils;
+mod htif;
+mod illegal_insn;
+mod misaligned;
+mod payload;
+mod plic;
+mod pmu;
 mod qemu_test;
+mod redirect;
+mod rfence;
 mod riscv_spec;
+mod sifive_uart;
+mod sse;
+mod sta;
 mod trap_stack;
 mod trap_vec;
 mod uart16550;
+mod unprivileged;
 
 mod constants {
-    /// 特权软件入口。
-    pub(crate) const SUPERVISOR_ENTRY: usize = 0x8020_0000;
+    /// SBI 占用的内存区域结束地址，由构建脚本根据 [`NUM_HART_MAX`] 决定。
+    pub(crate) const FIRMWARE_END: usize =
+        0x8000_0000 + parse_usize(env!("LEN_FIRMWARE").as_bytes());
+    /// 默认的特权软件入口，可在构建时以环境变量 `SUPERVISOR_ENTRY` 指定。
+    pub(crate) const SUPERVISOR_ENTRY: usize = match option_env!("SUPERVISOR_ENTRY") {
+        Some(entry) => parse_usize(entry.as_bytes()),
+        None => FIRMWARE_END,
+    };
     /// 每个硬件线程设置 16KiB 栈空间。
     pub(crate) const LEN_STACK_PER_HART: usize = 16 * 1024;
-    /// qemu-virt 最多 8 个硬件线程。
-    pub(crate) const NUM_HART_MAX: usize = 8;
+    /// 支持的硬件线程数量，可在构建时以环境变量 `NUM_HART_MAX` 指定，默认 8 个，最多 512 个。
+    pub(crate) const NUM_HART_MAX: usize = parse_usize(env!("NUM_HART_MAX").as_bytes());
+    /// 是否在交给特权软件的设备树中将 SBI 使用的控制台设备标记为 `reserved`，
+    /// 可在构建时以环境变量 `RESERVE_CONSOLE=1` 打开。
+    pub(crate) const RESERVE_CONSOLE: bool = match option_env!("RESERVE_CONSOLE") {
+        Some(value) => matches!(value.as_bytes(), b"1"),
+        None => false,
+    };
+    /// 最多支持 6 个内存区域，保证 PMP 表项够用。
+    pub(crate) const NUM_MEMORY_REGION_MAX: usize = 6;
+    /// 实现的 SBI 标准版本 v3.0，高 8 位为主版本号，低 24 位为次版本号。
+    pub(crate) const SBI_SPEC_VERSION: usize = 3 << 24;
+
+    /// 解析十进制或以 `0x` 开头的十六进制数。
+    const fn parse_usize(s: &[u8]) -> usize {
+        let (radix, mut i) = match s {
+            [b'0', b'x' | b'X', ..] => (16, 2),
+            _ => (10, 0),
+        };
+        let mut ans = 0;
+        while i < s.len() {
+            let digit = match s[i] {
+                b'0'..=b'9' => s[i] - b'0',
+                b'a'..=b'f' => s[i] - b'a' + 10,
+                b'A'..=b'F' => s[i] - b'A' + 10,
+                b'_' => {
+                    i += 1;
+                    continue;
+                }
+                _ => panic!("invalid digit"),
+            } as usize;
+            assert!(digit < radix, "invalid digit");
+            ans = ans * radix + digit;
+            i += 1;
+        }
+        ans
+    }
 }
 
 #[macro_use]
 extern crate rcore_console;
 
 use constants::*;
-use core::{
-    arch::asm,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, Ordering},
-};
+use core::{arch::asm, mem::MaybeUninit, sync::atomic::AtomicU32};
 use device_tree::BoardInfo;
 use fast_trap::{FastContext, FastResult};
 use riscv_spec::*;
 use rustsbi::{RustSBI, SbiRet};
 use spin::Once;
-use trap_stack::{local_hsm, local_remote_hsm, remote_hsm};
+use trap_stack::{local_hsm, local_mailbox, local_remote_hsm, remote_hsm};
 use trap_vec::trap_vec;
 
 /// 入口。
@@ -60,13 +115,19 @@ unsafe extern "C" fn _start() -> ! {
     )
 }
 
-/// rust 入口。
-extern "C" fn rust_main(hartid: usize, opaque: usize) {
-    static GENESIS: AtomicBool = AtomicBool::new(true);
-    static BOARD_INFO: Once<BoardInfo> = Once::new();
+/// 全局初始化标记，热重启时重新置位。
+///
+/// 由 [`trap_stack::locate`] 原子地认领，非零表示尚未被认领。
+static GENESIS: AtomicU32 = AtomicU32::new(1);
+/// 从设备树采集的板信息，热重启时重置。
+static BOARD_INFO: Once<BoardInfo> = Once::new();
 
+/// rust 入口。
+///
+/// `genesis` 由 [`trap_stack::locate`] 设置，表示此 hart 负责全局初始化。
+extern "C" fn rust_main(hartid: usize, opaque: usize, genesis: bool) {
     // 全局初始化过程
-    if GENESIS.swap(false, Ordering::AcqRel) {
+    if genesis {
         extern "C" {
             static mut sbss: u64;
             static mut ebss: u64;
@@ -79,57 +140,135 @@ extern "C" fn rust_main(hartid: usize, opaque: usize) {
                 ptr = ptr.offset(1);
             }
         }
+        qemu_test::genesis_started();
         // 解析设备树
-        let board_info = BOARD_INFO.call_once(|| device_tree::parse(opaque));
+        let board_info = BOARD_INFO.call_once(|| {
+            let mut board_info = device_tree::parse(opaque);
+            // 建立硬件线程表后其他 hart 才能定位自己的栈
+            trap_stack::init_harts(&board_info.harts);
+            // 向特权软件报告 SBI 占用的内存
+            board_info.dtb =
+                fdt_patch::reserve_firmware(&board_info, _start as usize..FIRMWARE_END);
+            board_info
+        });
         // 初始化外设
-        uart16550::init(board_info.uart.start);
+        if let Some(plic) = &board_info.plic {
+            plic::init(plic);
+        }
+        if let Some(info) = &board_info.console {
+            use device_tree::ConsoleKind::*;
+            console::init(match info.kind {
+                Ns16550 => uart16550::init(info),
+                SifiveUart => sifive_uart::init(info.reg.start),
+                Htif => htif::init(),
+            });
+            // 由 SBI 接收控制台中断
+            if let Some(irq) = info.irq.filter(|_| plic::exists()) {
+                plic::enable_machine(irq);
+                console::enable_interrupt();
+            }
+        }
         rcore_console::init_console(&Console);
         rcore_console::set_log_level(option_env!("LOG"));
-        clint::init(board_info.clint.start);
+        clint::init(board_info.clint.start, board_info.sstc);
+        fwft::init(board_info.svadu, board_info.ssnpm);
         qemu_test::init(board_info.test.start);
-        dbcn::init(SUPERVISOR_ENTRY..board_info.mem.end);
+        dbcn::init(supervisor_memory(board_info));
+        sta::init(supervisor_memory(board_info), board_info.timebase);
+        // 不在硬件线程表中的 hart 不能启动特权软件，交给第一个硬件线程
+        let boot_hart = match trap_stack::slot(hartid) {
+            Some(_) => hartid,
+            None => trap_stack::hart_ids()
+                .next()
+                .expect("no hart in `/cpus` can boot"),
+        };
+        sse::init(boot_hart);
+        // 内嵌特权软件优先，其次是设备树指定的入口，入口必须在特权软件可执行的内存中
+        let next_addr = board_info.next_addr.filter(|&addr| {
+            let executable = supervisor_executable(addr);
+            if !executable {
+                rcore_console::log::warn!(
+                    "ignore `rustsbi,next-addr` {addr:#x} outside supervisor memory"
+                );
+            }
+            executable
+        });
+        let supervisor_entry = payload::entry().or(next_addr).unwrap_or(SUPERVISOR_ENTRY);
         // 打印启动信息
         print!(
             "\
-[rustsbi] RustSBI version {ver_sbi}, adapting to RISC-V SBI v2.0.0
+[rustsbi] RustSBI version {ver_sbi}, adapting to RISC-V SBI v3.0.0
 {logo}
 [rustsbi] Implementation     : RustSBI-QEMU Version {ver_impl}
 [rustsbi] Platform Name      : {model}
 [rustsbi] Platform SMP       : {smp}
-[rustsbi] Platform Memory    : {mem:#x?}
-[rustsbi] Boot HART          : {hartid}
-[rustsbi] Device Tree Region : {dtb:#x?}
-[rustsbi] Firmware Address   : {firmware:#x}
-[rustsbi] Supervisor Address : {SUPERVISOR_ENTRY:#x}
 ",
             ver_sbi = rustsbi::VERSION,
             logo = rustsbi::LOGO,
             ver_impl = env!("CARGO_PKG_VERSION"),
             model = board_info.model,
             smp = board_info.smp,
-            mem = board_info.mem,
+        );
+        // 设备树可能没有内存节点
+        if board_info.mem.is_empty() {
+            println!("[rustsbi] Platform Memory    : none");
+        }
+        for (i, mem) in board_info.mem.iter().enumerate() {
+            let label = if i == 0 {
+                "Platform Memory    :"
+            } else {
+                "                    "
+            };
+            println!("[rustsbi] {label} {mem:#x?}");
+        }
+        print!(
+            "\
+[rustsbi] Boot HART          : {boot_hart}
+[rustsbi] Device Tree Region : {dtb:#x?}
+[rustsbi] Firmware Address   : {firmware:#x}
+[rustsbi] Supervisor Address : {supervisor_entry:#x}
+[rustsbi] Timer              : {timer}
+",
             dtb = board_info.dtb,
             firmware = _start as usize,
+            timer = if clint::sstc_enabled() {
+                "Sstc"
+            } else {
+                "CLINT"
+            },
         );
         // 初始化 SBI
         unsafe {
             SBI = MaybeUninit::new(FixedRustSBI {
                 clint: &clint::Clint,
+                fence: &rfence::RFence,
                 hsm: Hsm,
+                pmu: &pmu::Pmu,
                 reset: qemu_test::get(),
                 dbcn: dbcn::get(),
+                susp: Susp,
+                sta: sta::get(),
+                cppc: &cppc::Cppc,
             });
         }
         // 设置并打印 pmp
         set_pmp(board_info);
         hart_csr_utils::print_pmps();
+        // 设置内核入口
+        let supervisor = Supervisor {
+            start_addr: supervisor_entry,
+            opaque: board_info.dtb.start,
+        };
+        if boot_hart != hartid {
+            remote_hsm(boot_hart).unwrap().start(supervisor);
+            clint::set_msip(boot_hart);
+            loop {
+                unsafe { riscv::asm::wfi() };
+            }
+        }
         // 设置陷入栈
         trap_stack::prepare_for_trap();
-        // 设置内核入口
-        local_remote_hsm().start(Supervisor {
-            start_addr: SUPERVISOR_ENTRY,
-            opaque,
-        });
+        local_remote_hsm().start(supervisor);
     } else {
         // 设置 pmp
         set_pmp(BOARD_INFO.wait());
@@ -138,16 +277,21 @@ extern "C" fn rust_main(hartid: usize, opaque: usize) {
     }
     // 清理 clint
     clint::clear();
+    // 初始化性能计数器
+    pmu::init();
     // 准备启动调度
     unsafe {
         asm!("csrw mideleg,    {}", in(reg) !0);
         asm!("csrw medeleg,    {}", in(reg) !0);
-        asm!("csrw mcounteren, {}", in(reg) !0);
         use riscv::register::{medeleg, mtvec};
         medeleg::clear_supervisor_env_call();
         medeleg::clear_machine_env_call();
+        medeleg::clear_illegal_instruction();
+        medeleg::clear_load_misaligned();
+        medeleg::clear_store_misaligned();
         mtvec::write(trap_vec as _, mtvec::TrapMode::Vectored);
     }
+    qemu_test::hart_online();
 }
 
 #[inline(always)]
@@ -155,25 +299,83 @@ fn hart_id() -> usize {
     riscv::register::mhartid::read()
 }
 
+/// 特权软件可用的内存区域，不包括 SBI 所在的部分。
+fn supervisor_memory(board_info: &BoardInfo) -> device_tree::RangeList<NUM_MEMORY_REGION_MAX> {
+    let mut mem = board_info.mem.clone();
+    mem.iter_mut()
+        .filter(|r| r.contains(&FIRMWARE_END))
+        .for_each(|r| r.start = FIRMWARE_END);
+    mem
+}
+
 /// 设置 PMP。
+///
+/// 每个内存区域可读写执行，SBI 所在部分不可访问，区域之间和之后的地址视为外设。
 fn set_pmp(board_info: &BoardInfo) {
-    use riscv::register::*;
-    let mem = &board_info.mem;
-    unsafe {
-        pmpcfg0::set_pmp(0, Range::OFF, Permission::NONE, false);
-        pmpaddr0::write(0);
+    use riscv::register::{Permission, Range};
+    /// PMP 表项数量。
+    const NUM_PMP: usize = 16;
+    let mut i = 0;
+    let mut set = |range, perm, addr: usize| {
+        if i < NUM_PMP {
+            unsafe { write_pmp(i, range, perm, addr) };
+            i += 1;
+        }
+    };
+    set(Range::OFF, Permission::NONE, 0);
+    let mut end = 0;
+    for mem in board_info.mem.iter() {
         // 外设
-        pmpcfg0::set_pmp(1, Range::TOR, Permission::RW, false);
-        pmpaddr1::write(mem.start >> 2);
+        if mem.start > end {
+            set(Range::TOR, Permission::RW, mem.start >> 2);
+        }
         // SBI
-        pmpcfg0::set_pmp(2, Range::TOR, Permission::NONE, false);
-        pmpaddr2::write(SUPERVISOR_ENTRY >> 2);
+        if mem.contains(&FIRMWARE_END) {
+            set(Range::TOR, Permission::NONE, FIRMWARE_END >> 2);
+        }
         // 主存
-        pmpcfg0::set_pmp(3, Range::TOR, Permission::RWX, false);
-        pmpaddr3::write(mem.end >> 2);
-        // 其他
-        pmpcfg0::set_pmp(4, Range::TOR, Permission::RW, false);
-        pmpaddr4::write(1 << (usize::BITS - 1));
+        set(Range::TOR, Permission::RWX, mem.end >> 2);
+        end = mem.end;
+    }
+    // 其他
+    set(Range::TOR, Permission::RW, 1 << (usize::BITS - 1));
+    // 关闭剩余表项
+    for i in i..NUM_PMP {
+        unsafe { write_pmp(i, Range::OFF, Permission::NONE, 0) };
+    }
+}
+
+/// 设置第 `i` 个 PMP 表项，`addr` 是写入 `pmpaddr` 的值。
+unsafe fn write_pmp(
+    i: usize,
+    range: riscv::register::Range,
+    perm: riscv::register::Permission,
+    addr: usize,
+) {
+    use riscv::register::*;
+    if i < 8 {
+        pmpcfg0::set_pmp(i, range, perm, false);
+    } else {
+        pmpcfg2::set_pmp(i - 8, range, perm, false);
+    }
+    match i {
+        0x0 => pmpaddr0::write(addr),
+        0x1 => pmpaddr1::write(addr),
+        0x2 => pmpaddr2::write(addr),
+        0x3 => pmpaddr3::write(addr),
+        0x4 => pmpaddr4::write(addr),
+        0x5 => pmpaddr5::write(addr),
+        0x6 => pmpaddr6::write(addr),
+        0x7 => pmpaddr7::write(addr),
+        0x8 => pmpaddr8::write(addr),
+        0x9 => pmpaddr9::write(addr),
+        0xa => pmpaddr10::write(addr),
+        0xb => pmpaddr11::write(addr),
+        0xc => pmpaddr12::write(addr),
+        0xd => pmpaddr13::write(addr),
+        0xe => pmpaddr14::write(addr),
+        0xf => pmpaddr15::write(addr),
+        _ => unreachable!(),
     }
 }
 
@@ -188,8 +390,8 @@ extern "C" fn fast_handler(
     a7: usize,
 ) -> FastResult {
     use riscv::register::{
-        mcause::{self, Exception as E, Trap as T},
-        mtval, satp, sstatus,
+        mcause::{self, Exception as E, Interrupt as I, Trap as T},
+        mip, mtval, satp, sstatus,
     };
 
     #[inline]
@@ -210,38 +412,79 @@ extern "C" fn fast_handler(
                     *bits &= !mstatus::MPP;
                     *bits |= mstatus::MPIE | mstatus::MPP_SUPERVISOR;
                 });
-                mie::write(mie::MSIE | mie::MTIE);
+                let mut bits = mie::MSIE;
+                // 使用 Sstc 时不需要代理 machine timer 中断
+                if !clint::sstc_enabled() {
+                    bits |= mie::MTIE;
+                }
+                if plic::exists() {
+                    bits |= mie::MEIE;
+                }
+                mie::write(bits);
+                fwft::reset();
+                sse::reset();
+                dbtr::reset();
                 break boot(ctx, supervisor.start_addr, supervisor.opaque);
             }
             Err(rustsbi::spec::hsm::HART_STOP) => {
+                let begin = clint::read_mtime();
                 mie::write(mie::MSIE);
                 unsafe { riscv::asm::wfi() };
+                // 停止的 hart 也要离开这里，热重启将清零它使用的全局状态
+                qemu_test::check_warm_reboot();
                 clint::clear_msip();
+                // 停止前收到的远程同步请求
+                rfence::serve();
+                sta::steal(begin);
             }
             _ => match mcause::read().cause() {
                 // SBI call
                 T::Exception(E::SupervisorEnvCall) => {
                     use sbi_spec::{base, hsm, legacy};
-                    let mut ret = unsafe { SBI.assume_init_mut() }.handle_ecall(
-                        a7,
-                        a6,
-                        [ctx.a0(), a1, a2, a3, a4, a5],
-                    );
+                    // 事件处理完成，回到被打断的位置
+                    if (a7, a6) == (sse::EID_SSE, sse::COMPLETE) {
+                        let mut regs = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+                        if let Err(ret) = sse::complete(&mut regs) {
+                            (regs[0], regs[1]) = (ret.error, ret.value);
+                            mepc::next();
+                        }
+                        sse::deliver(&mut regs);
+                        ctx.regs().a = regs;
+                        break ctx.restore();
+                    }
+                    // rustsbi 0.4 尚未支持这些扩展，在这里直接分发
+                    let mut ret = match a7 {
+                        fwft::EID_FWFT => fwft::handle(a6, [ctx.a0(), a1, a2]),
+                        dbtr::EID_DBTR => dbtr::handle(a6, [ctx.a0(), a1, a2]),
+                        sse::EID_SSE => sse::handle(a6, [ctx.a0(), a1, a2, a3, a4, a5]),
+                        _ => unsafe { SBI.assume_init_mut() }.handle_ecall(
+                            a7,
+                            a6,
+                            [ctx.a0(), a1, a2, a3, a4, a5],
+                        ),
+                    };
                     if ret.is_ok() {
+                        // 从不可恢复挂起中唤醒，重新进入特权软件
+                        if let Some(resume) = trap_stack::take_resume() {
+                            dbtr::restore();
+                            break boot(ctx, resume.start_addr, resume.opaque);
+                        }
                         match (a7, a6) {
                             // 关闭
                             (hsm::EID_HSM, hsm::HART_STOP) => continue,
-                            // 不可恢复挂起
-                            (hsm::EID_HSM, hsm::HART_SUSPEND)
-                                if matches!(ctx.a0() as u32, hsm::suspend_type::NON_RETENTIVE) =>
-                            {
-                                break boot(ctx, a1, a2);
+                            // 直接分发的扩展来自 SBI v3.0，不能报告 RustSBI 实现的版本
+                            (base::EID_BASE, base::GET_SBI_SPEC_VERSION) => {
+                                ret.value = SBI_SPEC_VERSION;
                             }
-                            // legacy console 探测
+                            // legacy console 和直接分发的扩展探测
                             (base::EID_BASE, base::PROBE_EXTENSION)
                                 if matches!(
                                     ctx.a0(),
-                                    legacy::LEGACY_CONSOLE_PUTCHAR | legacy::LEGACY_CONSOLE_GETCHAR
+                                    legacy::LEGACY_CONSOLE_PUTCHAR
+                                        | legacy::LEGACY_CONSOLE_GETCHAR
+                                        | fwft::EID_FWFT
+                                        | sse::EID_SSE
+                                        | dbtr::EID_DBTR
                                 ) =>
                             {
                                 ret.value = 1;
@@ -257,20 +500,59 @@ extern "C" fn fast_handler(
                             }
                             legacy::LEGACY_CONSOLE_GETCHAR => {
                                 let mut c = 0u8;
-                                let uart = uart16550::UART.lock();
-                                loop {
-                                    if uart.get().read(core::slice::from_mut(&mut c)) == 1 {
-                                        ret.error = c as _;
-                                        ret.value = a1;
-                                        break;
-                                    }
-                                }
+                                // 没有输入时返回 -1
+                                ret.error = if console::read(core::slice::from_mut(&mut c)) == 1 {
+                                    c as _
+                                } else {
+                                    usize::MAX
+                                };
+                                ret.value = a1;
                             }
                             _ => {}
                         }
                     }
-                    ctx.regs().a = [ret.error, ret.value, a2, a3, a4, a5, a6, a7];
+                    let mut regs = [ret.error, ret.value, a2, a3, a4, a5, a6, a7];
                     mepc::next();
+                    sse::deliver(&mut regs);
+                    ctx.regs().a = regs;
+                    break ctx.restore();
+                }
+                // 核间消息
+                T::Interrupt(I::MachineSoft) => {
+                    qemu_test::check_warm_reboot();
+                    clint::clear_msip();
+                    if local_mailbox().take_ipi() {
+                        unsafe { mip::set_ssoft() };
+                    }
+                    rfence::serve();
+                    let mut regs = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+                    sse::deliver(&mut regs);
+                    ctx.regs().a = regs;
+                    break ctx.restore();
+                }
+                // 外部中断
+                T::Interrupt(I::MachineExternal) => {
+                    handle_external();
+                    ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+                    break ctx.restore();
+                }
+                // 非对齐访存
+                T::Exception(E::LoadMisaligned | E::StoreMisaligned) => {
+                    ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+                    break ctx.continue_with(misaligned::emulate, ());
+                }
+                // 非法指令
+                T::Exception(E::IllegalInstruction) => {
+                    ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
+                    break ctx.continue_with(illegal_insn::emulate, ());
+                }
+                // 其他 S/U 态异常转发给特权软件
+                T::Exception(_) if !redirect::from_machine() => {
+                    redirect::redirect(unprivileged::TrapInfo {
+                        cause: mcause::read().bits(),
+                        tval: mtval::read(),
+                    });
+                    ctx.regs().a = [ctx.a0(), a1, a2, a3, a4, a5, a6, a7];
                     break ctx.restore();
                 }
                 // 其他陷入
@@ -320,20 +602,12 @@ struct Console;
 impl rcore_console::Console for Console {
     #[inline]
     fn put_char(&self, c: u8) {
-        let uart = uart16550::UART.lock();
-        while uart.get().write(&[c]) == 0 {
-            core::hint::spin_loop();
-        }
+        console::write_all(&[c]);
     }
 
     #[inline]
     fn put_str(&self, s: &str) {
-        let uart = uart16550::UART.lock();
-        let mut bytes = s.as_bytes();
-        while !bytes.is_empty() {
-            let count = uart.get().write(bytes);
-            bytes = &bytes[count..];
-        }
+        console::write_all(s.as_bytes());
     }
 }
 
@@ -343,9 +617,14 @@ static mut SBI: MaybeUninit<FixedRustSBI> = MaybeUninit::uninit();
 struct FixedRustSBI<'a> {
     #[rustsbi(ipi, timer)]
     clint: &'a clint::Clint,
+    fence: &'a rfence::RFence,
     hsm: Hsm,
+    pmu: &'a pmu::Pmu,
     reset: &'a qemu_test::QemuTest,
     dbcn: &'a dbcn::DBCN,
+    susp: Susp,
+    sta: &'a sta::Sta,
+    cppc: &'a cppc::Cppc,
 }
 
 struct Hsm;
@@ -379,15 +658,119 @@ impl rustsbi::Hsm for Hsm {
         }
     }
 
-    fn hart_suspend(&self, suspend_type: u32, _resume_addr: usize, _opaque: usize) -> SbiRet {
+    fn hart_suspend(&self, suspend_type: u32, resume_addr: usize, opaque: usize) -> SbiRet {
         use rustsbi::spec::hsm::suspend_type::{NON_RETENTIVE, RETENTIVE};
-        if matches!(suspend_type, NON_RETENTIVE | RETENTIVE) {
-            local_hsm().suspend();
-            unsafe { riscv::asm::wfi() };
-            local_hsm().resume();
-            SbiRet::success(0)
+        match suspend_type {
+            RETENTIVE => {
+                suspend();
+                SbiRet::success(0)
+            }
+            NON_RETENTIVE => {
+                if !supervisor_executable(resume_addr) {
+                    return SbiRet::invalid_address();
+                }
+                suspend();
+                // 特权软件状态不再保留，返回时从恢复地址重新进入
+                trap_stack::set_resume(Supervisor {
+                    start_addr: resume_addr,
+                    opaque,
+                });
+                SbiRet::success(0)
+            }
+            // 平台自定义的可恢复和不可恢复挂起
+            0x1000_0000..=0x7fff_ffff | 0x9000_0000..=0xffff_ffff => SbiRet::not_supported(),
+            _ => SbiRet::invalid_param(),
+        }
+    }
+}
+
+struct Susp;
+
+impl rustsbi::Susp for Susp {
+    fn system_suspend(&self, sleep_type: u32, resume_addr: usize, opaque: usize) -> SbiRet {
+        use rustsbi::spec::hsm::hart_state::STOPPED;
+        /// 挂起到内存。
+        const SUSPEND_TO_RAM: u32 = 0;
+        if sleep_type != SUSPEND_TO_RAM {
+            return SbiRet::invalid_param();
+        }
+        if !supervisor_executable(resume_addr) {
+            return SbiRet::invalid_address();
+        }
+        // 除调用者之外的 hart 都要处于关闭状态
+        let hartid = hart_id();
+        if trap_stack::hart_ids()
+            .filter(|&i| i != hartid)
+            .any(|i| remote_hsm(i).unwrap().sbi_get_status() != STOPPED)
+        {
+            return SbiRet::denied();
+        }
+        let begin = clint::read_mtime();
+        suspend();
+        sta::steal(begin);
+        trap_stack::set_resume(Supervisor {
+            start_addr: resume_addr,
+            opaque,
+        });
+        SbiRet::success(0)
+    }
+}
+
+/// 挂起此 hart，直到有允许的中断等待处理或收到核间中断。
+///
+/// 挂起期间处理远程同步请求，这些请求不会唤醒 hart。
+fn suspend() {
+    use riscv::register::mip;
+    local_hsm().suspend();
+    loop {
+        let pending = mip::read().bits() & mie::read();
+        // SBI 处理的外部中断不唤醒
+        if pending & mie::MEIE != 0 {
+            handle_external();
+            continue;
+        }
+        if pending & !mie::MSIE != 0 {
+            break;
+        }
+        if pending & mie::MSIE != 0 {
+            qemu_test::check_warm_reboot();
+            clint::clear_msip();
+            rfence::serve();
+            if local_mailbox().take_ipi() {
+                unsafe { mip::set_ssoft() };
+                break;
+            }
+            // 注入的软件事件在返回特权软件时投递
+            if sse::pending() {
+                break;
+            }
         } else {
-            SbiRet::not_supported()
+            unsafe { riscv::asm::wfi() };
         }
     }
+    local_hsm().resume();
+}
+
+/// 处理 SBI 接收的外部中断。
+fn handle_external() {
+    while let Some(source) = plic::claim() {
+        if BOARD_INFO.wait().console.as_ref().and_then(|c| c.irq) == Some(source) {
+            console::handle_interrupt();
+        }
+        plic::complete(source);
+    }
+}
+
+/// 地址是否位于特权软件可执行的内存。
+fn supervisor_executable(addr: usize) -> bool {
+    supervisor_memory(BOARD_INFO.wait())
+        .iter()
+        .any(|r| r.contains(&addr))
+}
+
+/// 从 `start` 开始的 `len` 字节是否完整位于特权软件可用的一块内存，用于检查共享内存和缓冲区。
+fn supervisor_accessible(start: usize, len: usize) -> bool {
+    start.checked_add(len).map_or(false, |end| {
+        supervisor_memory(BOARD_INFO.wait()).contains_range(start, end)
+    })
 }
@@ -0,0 +1,127 @@
+use crate::{
+    flow_context::{read_reg, write_reg},
+    hart_id, pmu, redirect,
+    riscv_spec::mepc,
+    trap_stack::count_misaligned,
+    unprivileged::{self, TrapInfo},
+};
+use fast_trap::{EntireContext, EntireResult, FlowContext};
+use riscv::register::{mcause, mtval};
+use sbi_spec::pmu::firmware_event;
+
+/// 非对齐访存。
+enum Access {
+    Load {
+        rd: usize,
+        width: usize,
+        signed: bool,
+    },
+    Store {
+        rs2: usize,
+        width: usize,
+    },
+}
+
+#[inline]
+const fn load(rd: usize, width: usize, signed: bool) -> Access {
+    Access::Load { rd, width, signed }
+}
+
+#[inline]
+const fn store(rs2: usize, width: usize) -> Access {
+    Access::Store { rs2, width }
+}
+
+/// 模拟非对齐访存的完整路径。
+///
+/// 在特权软件地址空间中逐字节完成访存，结果写回陷入上下文。
+/// 无法识别的指令和访存中发生的异常转发给特权软件。
+pub(crate) extern "C" fn emulate(ctx: EntireContext) -> EntireResult {
+    let (mut ctx, _) = ctx.split();
+    if let Err(trap) = emulate_access(ctx.regs()) {
+        redirect::redirect(trap);
+    }
+    ctx.restore()
+}
+
+fn emulate_access(regs: &mut FlowContext) -> Result<(), TrapInfo> {
+    let epc = mepc::read();
+    let addr = mtval::read();
+    let unsupported = TrapInfo {
+        cause: mcause::read().bits(),
+        tval: addr,
+    };
+    let (access, len) = decode(unprivileged::load_insn(epc)?).ok_or(unsupported)?;
+    match access {
+        Access::Load { rd, width, signed } => {
+            let mut val = 0usize;
+            for i in 0..width {
+                val |= (unprivileged::load_u8(addr + i)? as usize) << (8 * i);
+            }
+            if signed && width < core::mem::size_of::<usize>() {
+                let shift = usize::BITS as usize - 8 * width;
+                val = ((val << shift) as isize >> shift) as usize;
+            }
+            write_reg(regs, rd, val);
+            pmu::record(firmware_event::MISALIGNED_LOAD);
+        }
+        Access::Store { rs2, width } => {
+            let val = read_reg(regs, rs2);
+            for i in 0..width {
+                unprivileged::store_u8(addr + i, (val >> (8 * i)) as u8)?;
+            }
+            pmu::record(firmware_event::MISALIGNED_STORE);
+        }
+    }
+    mepc::write(epc + len);
+    // 日志宏只在级别启用时求值参数，计数要先完成
+    let count = count_misaligned();
+    rcore_console::log::trace!(
+        "hart {} emulated misaligned access to {addr:#x} at {epc:#x} ({count} times)",
+        hart_id(),
+    );
+    Ok(())
+}
+
+/// 解码访存指令，返回访存类型和指令长度。
+///
+/// 单字节访存不会非对齐，不需要解码。
+fn decode(insn: u32) -> Option<(Access, usize)> {
+    if insn & 0b11 == 0b11 {
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let rs2 = ((insn >> 20) & 0x1f) as usize;
+        let access = match (insn & 0x7f, (insn >> 12) & 0b111) {
+            // lh, lw, ld, lhu, lwu
+            (0b000_0011, 0b001) => load(rd, 2, true),
+            (0b000_0011, 0b010) => load(rd, 4, true),
+            (0b000_0011, 0b011) => load(rd, 8, false),
+            (0b000_0011, 0b101) => load(rd, 2, false),
+            (0b000_0011, 0b110) => load(rd, 4, false),
+            // sh, sw, sd
+            (0b010_0011, 0b001) => store(rs2, 2),
+            (0b010_0011, 0b010) => store(rs2, 4),
+            (0b010_0011, 0b011) => store(rs2, 8),
+            _ => return None,
+        };
+        Some((access, 4))
+    } else {
+        // 压缩指令的 rd' 和 rs2' 位于同一位置
+        let rd_ = (((insn >> 2) & 0b111) + 8) as usize;
+        let rd = ((insn >> 7) & 0x1f) as usize;
+        let rs2 = ((insn >> 2) & 0x1f) as usize;
+        let access = match (insn & 0b11, (insn >> 13) & 0b111) {
+            // c.lw, c.ld, c.sw, c.sd
+            (0b00, 0b010) => load(rd_, 4, true),
+            (0b00, 0b011) => load(rd_, 8, false),
+            (0b00, 0b110) => store(rd_, 4),
+            (0b00, 0b111) => store(rd_, 8),
+            // c.lwsp, c.ldsp, c.swsp, c.sdsp
+            (0b10, 0b010) => load(rd, 4, true),
+            (0b10, 0b011) => load(rd, 8, false),
+            (0b10, 0b110) => store(rs2, 4),
+            (0b10, 0b111) => store(rs2, 8),
+            _ => return None,
+        };
+        Some((access, 2))
+    }
+}
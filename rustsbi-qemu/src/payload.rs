@@ -0,0 +1,21 @@
+// 构建时设置环境变量 `PAYLOAD` 为特权软件二进制文件路径，
+// 它将被链接到 SBI 之后的 `FIRMWARE_END` 处。
+core::arch::global_asm!(include_str!(concat!(env!("OUT_DIR"), "/payload.S")));
+
+extern "C" {
+    static payload_start: u8;
+    static payload_end: u8;
+}
+
+/// 内嵌特权软件的入口，未内嵌时返回 `None`。
+pub(crate) fn entry() -> Option<usize> {
+    let range = range();
+    (!range.is_empty()).then_some(range.start)
+}
+
+/// 内嵌特权软件占用的内存，未内嵌时为空。
+pub(crate) fn range() -> core::ops::Range<usize> {
+    let start = unsafe { &payload_start as *const u8 as usize };
+    let end = unsafe { &payload_end as *const u8 as usize };
+    start..end
+}
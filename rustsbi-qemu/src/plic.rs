@@ -0,0 +1,127 @@
+use crate::{device_tree::PlicInfo, hart_id, trap_stack::slot, NUM_HART_MAX};
+use spin::Once;
+
+/// 寄存器偏移。
+const PRIORITY: usize = 0;
+const ENABLE: usize = 0x2000;
+const ENABLE_PER_CONTEXT: usize = 0x80;
+const CONTEXT: usize = 0x20_0000;
+const CONTEXT_PER_CONTEXT: usize = 0x1000;
+const THRESHOLD: usize = 0;
+const CLAIM: usize = 4;
+
+/// 平台级中断控制器。
+struct Plic {
+    base: usize,
+    ndev: u32,
+    /// 按栈序号排列的每个 hart 的 M 态上下文。
+    machine: [Option<u16>; NUM_HART_MAX],
+}
+
+static PLIC: Once<Plic> = Once::new();
+
+/// 初始化中断控制器。
+///
+/// 所有中断源的优先级置 0，所有上下文的中断都不使能，阈值置 0。
+/// 特权软件使用中断源时自行设置优先级和 S 态上下文；M 态上下文只使能 SBI 自己处理的中断源。
+pub(crate) fn init(info: &PlicInfo) {
+    let plic = PLIC.call_once(|| {
+        let mut machine = [None; NUM_HART_MAX];
+        for c in info.contexts.iter() {
+            if let Some(slot) = slot(c.hart as _) {
+                machine[slot] = c.machine;
+            }
+        }
+        Plic {
+            base: info.reg.start,
+            ndev: info.ndev,
+            machine,
+        }
+    });
+    for source in 1..=plic.ndev {
+        plic.set_priority(source, 0);
+    }
+    for c in info.contexts.iter() {
+        for context in [c.machine, c.supervisor].into_iter().flatten() {
+            for word in 0..=plic.ndev / 32 {
+                unsafe { plic.enable(context, word).write_volatile(0) };
+            }
+            unsafe { plic.context(context, THRESHOLD).write_volatile(0) };
+        }
+    }
+}
+
+/// 是否有中断控制器。
+#[inline]
+pub(crate) fn exists() -> bool {
+    PLIC.is_completed()
+}
+
+impl Plic {
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.base + offset) as _
+    }
+
+    #[inline]
+    fn set_priority(&self, source: u32, priority: u32) {
+        unsafe {
+            self.reg(PRIORITY + source as usize * 4)
+                .write_volatile(priority)
+        };
+    }
+
+    /// `context` 的第 `word` 个使能寄存器。
+    #[inline]
+    fn enable(&self, context: u16, word: u32) -> *mut u32 {
+        self.reg(ENABLE + context as usize * ENABLE_PER_CONTEXT + word as usize * 4)
+    }
+
+    #[inline]
+    fn context(&self, context: u16, offset: usize) -> *mut u32 {
+        self.reg(CONTEXT + context as usize * CONTEXT_PER_CONTEXT + offset)
+    }
+
+    /// 此 hart 的 M 态上下文。
+    #[inline]
+    fn local_machine(&self) -> Option<u16> {
+        self.machine[slot(hart_id())?]
+    }
+}
+
+/// 使所有 hart 的 M 态接收 `source` 中断，不存在的中断源被忽略。
+pub(crate) fn enable_machine(source: u32) {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    if source == 0 || source > plic.ndev {
+        return;
+    }
+    plic.set_priority(source, 1);
+    for context in plic.machine.iter().flatten() {
+        let enable = plic.enable(*context, source / 32);
+        unsafe { enable.write_volatile(enable.read_volatile() | 1 << (source % 32)) };
+    }
+}
+
+/// 认领此 hart 的 M 态上下文中等待处理的中断。
+#[inline]
+pub(crate) fn claim() -> Option<u32> {
+    let plic = PLIC.get()?;
+    let claim = plic.context(plic.local_machine()?, CLAIM);
+    match unsafe { claim.read_volatile() } {
+        0 => None,
+        source => Some(source),
+    }
+}
+
+/// 完成 `source` 中断的处理。
+#[inline]
+pub(crate) fn complete(source: u32) {
+    let Some(plic) = PLIC.get() else {
+        return;
+    };
+    if let Some(context) = plic.local_machine() {
+        unsafe { plic.context(context, CLAIM).write_volatile(source) };
+    }
+}
@@ -0,0 +1,356 @@
+use crate::{
+    riscv_spec::{mcounteren, mcountinhibit, mcycle, minstret},
+    trap_stack::local_pmu,
+};
+use core::sync::atomic::{AtomicU32, Ordering};
+use rustsbi::SbiRet;
+use sbi_spec::pmu::{event_type, firmware_event, hardware_event};
+
+/// 硬件计数器数量：cycle、time、instret 和 hpmcounter3..31。
+const NUM_HARDWARE_COUNTERS: usize = 32;
+/// 固件计数器数量。
+const NUM_FIRMWARE_COUNTERS: usize = 16;
+/// 计数器总数。
+const NUM_COUNTERS: usize = NUM_HARDWARE_COUNTERS + NUM_FIRMWARE_COUNTERS;
+
+/// `counter_config_matching` 的标志位。
+mod config_flags {
+    pub const SKIP_MATCH: usize = 1 << 0;
+    pub const CLEAR_VALUE: usize = 1 << 1;
+    pub const AUTO_START: usize = 1 << 2;
+}
+
+/// `counter_start` 的标志位。
+mod start_flags {
+    pub const SET_INIT_VALUE: usize = 1 << 0;
+}
+
+/// `counter_stop` 的标志位。
+mod stop_flags {
+    pub const RESET: usize = 1 << 0;
+}
+
+/// QEMU 可编程计数器支持的缓存事件：DTLB 读缺失、DTLB 写缺失、ITLB 读缺失。
+const QEMU_CACHE_EVENTS: [usize; 3] = [0x1_0019, 0x1_001b, 0x1_0021];
+
+/// 平台实现的硬件计数器掩码。
+static HARDWARE_COUNTERS: AtomicU32 = AtomicU32::new(0);
+
+/// 探测并初始化当前硬件线程的计数器。
+///
+/// cycle 和 instret 保持运行，可编程计数器全部停止，并允许特权软件读取所有已实现的计数器。
+pub(crate) fn init() {
+    // mcountinhibit 是 WARL 的，未实现的计数器对应位读出为 0
+    mcountinhibit::write(!0);
+    let implemented = mcountinhibit::read() | mcountinhibit::CY | mcountinhibit::IR;
+    HARDWARE_COUNTERS.store(implemented | mcounteren::TM, Ordering::Relaxed);
+    mcountinhibit::write(implemented & !(mcountinhibit::CY | mcountinhibit::IR));
+    mcounteren::write(implemented | mcounteren::TM);
+}
+
+/// 记录一次固件事件。
+#[inline]
+pub(crate) fn record(event: usize) {
+    let state = local_pmu();
+    for i in 0..NUM_FIRMWARE_COUNTERS {
+        if state.started & (1 << (NUM_HARDWARE_COUNTERS + i)) != 0 && state.fw_event[i] == event {
+            state.fw_value[i] = state.fw_value[i].wrapping_add(1);
+        }
+    }
+}
+
+/// 硬件线程的性能计数器状态。
+pub(crate) struct PmuState {
+    /// 已分配给事件的计数器。
+    active: u64,
+    /// 已启动的计数器。
+    started: u64,
+    /// 固件计数器对应的事件。
+    fw_event: [usize; NUM_FIRMWARE_COUNTERS],
+    /// 固件计数器的值。
+    fw_value: [u64; NUM_FIRMWARE_COUNTERS],
+}
+
+impl PmuState {
+    pub const fn new() -> Self {
+        Self {
+            active: 0,
+            started: 0,
+            fw_event: [0; NUM_FIRMWARE_COUNTERS],
+            fw_value: [0; NUM_FIRMWARE_COUNTERS],
+        }
+    }
+}
+
+pub(crate) struct Pmu;
+
+impl rustsbi::Pmu for Pmu {
+    #[inline]
+    fn num_counters(&self) -> usize {
+        NUM_COUNTERS
+    }
+
+    fn counter_get_info(&self, counter_idx: usize) -> SbiRet {
+        const CSR_CYCLE: usize = 0xc00;
+        const TYPE_FIRMWARE: usize = 1 << (usize::BITS - 1);
+        match counter_idx {
+            // time 不能用于事件计数
+            1 => SbiRet::invalid_param(),
+            i if i < NUM_HARDWARE_COUNTERS && is_implemented(i) => {
+                SbiRet::success((63 << 12) | (CSR_CYCLE + i))
+            }
+            i if (NUM_HARDWARE_COUNTERS..NUM_COUNTERS).contains(&i) => {
+                SbiRet::success(TYPE_FIRMWARE)
+            }
+            _ => SbiRet::invalid_param(),
+        }
+    }
+
+    fn counter_config_matching(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        config_flags: usize,
+        event_idx: usize,
+        event_data: u64,
+    ) -> SbiRet {
+        let Some(mask) = counter_mask(counter_idx_base, counter_idx_mask) else {
+            return SbiRet::invalid_param();
+        };
+        let state = local_pmu();
+        let counter = if config_flags & config_flags::SKIP_MATCH != 0 {
+            let counter = mask.trailing_zeros() as usize;
+            if state.active & (1 << counter) == 0 {
+                return SbiRet::invalid_param();
+            }
+            counter
+        } else {
+            let free = mask & !state.active;
+            let counter = match (event_idx >> 16) & 0xf {
+                event_type::HARDWARE_GENERAL => match event_idx & 0xffff {
+                    hardware_event::CPU_CYCLES if free & (1 << 0) != 0 => Some(0),
+                    hardware_event::INSTRUCTIONS if free & (1 << 2) != 0 => Some(2),
+                    hardware_event::CPU_CYCLES | hardware_event::INSTRUCTIONS => {
+                        select_programmable(free, event_idx)
+                    }
+                    _ => return SbiRet::not_supported(),
+                },
+                event_type::HARDWARE_CACHE if QEMU_CACHE_EVENTS.contains(&event_idx) => {
+                    select_programmable(free, event_idx)
+                }
+                event_type::HARDWARE_CACHE => return SbiRet::not_supported(),
+                event_type::HARDWARE_RAW => select_programmable(free, event_data as _),
+                event_type::FIRMWARE => {
+                    let code = event_idx & 0xffff;
+                    if code > firmware_event::HFENCE_VVMA_ASID_RECEIVED {
+                        return SbiRet::not_supported();
+                    }
+                    let fw = free >> NUM_HARDWARE_COUNTERS;
+                    (fw != 0).then(|| {
+                        let i = fw.trailing_zeros() as usize;
+                        state.fw_event[i] = code;
+                        NUM_HARDWARE_COUNTERS + i
+                    })
+                }
+                _ => return SbiRet::invalid_param(),
+            };
+            match counter {
+                Some(counter) => counter,
+                None => return SbiRet::not_supported(),
+            }
+        };
+        state.active |= 1 << counter;
+        if config_flags & config_flags::CLEAR_VALUE != 0 {
+            write_counter(state, counter, 0);
+        }
+        if config_flags & config_flags::AUTO_START != 0 {
+            start_counter(state, counter);
+        }
+        SbiRet::success(counter)
+    }
+
+    fn counter_start(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        start_flags: usize,
+        initial_value: u64,
+    ) -> SbiRet {
+        let Some(mask) = counter_mask(counter_idx_base, counter_idx_mask) else {
+            return SbiRet::invalid_param();
+        };
+        let state = local_pmu();
+        if mask & !state.active != 0 {
+            return SbiRet::invalid_param();
+        }
+        if mask & state.started != 0 {
+            return SbiRet::already_started();
+        }
+        for counter in bits(mask) {
+            if start_flags & start_flags::SET_INIT_VALUE != 0 {
+                write_counter(state, counter, initial_value);
+            }
+            start_counter(state, counter);
+        }
+        SbiRet::success(0)
+    }
+
+    fn counter_stop(
+        &self,
+        counter_idx_base: usize,
+        counter_idx_mask: usize,
+        stop_flags: usize,
+    ) -> SbiRet {
+        let Some(mask) = counter_mask(counter_idx_base, counter_idx_mask) else {
+            return SbiRet::invalid_param();
+        };
+        let state = local_pmu();
+        if mask & !state.active != 0 {
+            return SbiRet::invalid_param();
+        }
+        if mask & !state.started != 0 {
+            return SbiRet::already_stopped();
+        }
+        let hardware = mask as u32;
+        if hardware != 0 {
+            mcountinhibit::set(hardware);
+        }
+        state.started &= !mask;
+        if stop_flags & stop_flags::RESET != 0 {
+            state.active &= !mask;
+        }
+        SbiRet::success(0)
+    }
+
+    fn counter_fw_read(&self, counter_idx: usize) -> SbiRet {
+        let state = local_pmu();
+        match counter_idx.checked_sub(NUM_HARDWARE_COUNTERS) {
+            Some(i) if i < NUM_FIRMWARE_COUNTERS && state.active & (1 << counter_idx) != 0 => {
+                SbiRet::success(state.fw_value[i] as _)
+            }
+            _ => SbiRet::invalid_param(),
+        }
+    }
+}
+
+/// 检查计数器号是否对应已实现的硬件计数器。
+#[inline]
+fn is_implemented(counter: usize) -> bool {
+    HARDWARE_COUNTERS.load(Ordering::Relaxed) & (1 << counter) != 0
+}
+
+/// 将 `counter_idx_base` 和 `counter_idx_mask` 转换为计数器掩码。
+///
+/// 如果掩码为空或包含不存在的计数器，返回 `None`。
+fn counter_mask(base: usize, mask: usize) -> Option<u64> {
+    let mut ans = 0u64;
+    for i in 0..usize::BITS as usize {
+        if mask & (1 << i) != 0 {
+            let counter = base.checked_add(i)?;
+            let valid = if counter < NUM_HARDWARE_COUNTERS {
+                counter != 1 && is_implemented(counter)
+            } else {
+                counter < NUM_COUNTERS
+            };
+            if !valid {
+                return None;
+            }
+            ans |= 1 << counter;
+        }
+    }
+    (ans != 0).then_some(ans)
+}
+
+/// 遍历掩码中的计数器。
+#[inline]
+fn bits(mut mask: u64) -> impl Iterator<Item = usize> {
+    core::iter::from_fn(move || {
+        (mask != 0).then(|| {
+            let i = mask.trailing_zeros() as usize;
+            mask &= mask - 1;
+            i
+        })
+    })
+}
+
+/// 在空闲的可编程计数器中选择一个，并设置其事件。
+fn select_programmable(free: u64, event: usize) -> Option<usize> {
+    let programmable = free & !0b111 & ((1 << NUM_HARDWARE_COUNTERS) - 1);
+    (programmable != 0).then(|| {
+        let counter = programmable.trailing_zeros() as usize;
+        write_hpm(counter, event, Hpm::Event);
+        counter
+    })
+}
+
+/// 设置计数器的值。
+fn write_counter(state: &mut PmuState, counter: usize, value: u64) {
+    match counter {
+        0 => mcycle::write(value),
+        2 => minstret::write(value),
+        i if i < NUM_HARDWARE_COUNTERS => write_hpm(i, value as _, Hpm::Counter),
+        i => state.fw_value[i - NUM_HARDWARE_COUNTERS] = value,
+    }
+}
+
+/// 启动计数器。
+#[inline]
+fn start_counter(state: &mut PmuState, counter: usize) {
+    if counter < NUM_HARDWARE_COUNTERS {
+        mcountinhibit::clear(1 << counter);
+    }
+    state.started |= 1 << counter;
+}
+
+/// 可编程计数器的寄存器。
+enum Hpm {
+    Counter,
+    Event,
+}
+
+/// 写可编程计数器的寄存器。
+fn write_hpm(counter: usize, bits: usize, hpm: Hpm) {
+    use riscv::register::*;
+    macro_rules! write_hpm {
+        ($($i:literal => $counter:ident, $event:ident;)+) => {
+            match (counter, hpm) {
+                $(
+                    ($i, Hpm::Counter) => $counter::write(bits),
+                    ($i, Hpm::Event) => $event::write(bits),
+                )+
+                _ => unreachable!(),
+            }
+        };
+    }
+    write_hpm! {
+         3 => mhpmcounter3,  mhpmevent3;
+         4 => mhpmcounter4,  mhpmevent4;
+         5 => mhpmcounter5,  mhpmevent5;
+         6 => mhpmcounter6,  mhpmevent6;
+         7 => mhpmcounter7,  mhpmevent7;
+         8 => mhpmcounter8,  mhpmevent8;
+         9 => mhpmcounter9,  mhpmevent9;
+        10 => mhpmcounter10, mhpmevent10;
+        11 => mhpmcounter11, mhpmevent11;
+        12 => mhpmcounter12, mhpmevent12;
+        13 => mhpmcounter13, mhpmevent13;
+        14 => mhpmcounter14, mhpmevent14;
+        15 => mhpmcounter15, mhpmevent15;
+        16 => mhpmcounter16, mhpmevent16;
+        17 => mhpmcounter17, mhpmevent17;
+        18 => mhpmcounter18, mhpmevent18;
+        19 => mhpmcounter19, mhpmevent19;
+        20 => mhpmcounter20, mhpmevent20;
+        21 => mhpmcounter21, mhpmevent21;
+        22 => mhpmcounter22, mhpmevent22;
+        23 => mhpmcounter23, mhpmevent23;
+        24 => mhpmcounter24, mhpmevent24;
+        25 => mhpmcounter25, mhpmevent25;
+        26 => mhpmcounter26, mhpmevent26;
+        27 => mhpmcounter27, mhpmevent27;
+        28 => mhpmcounter28, mhpmevent28;
+        29 => mhpmcounter29, mhpmevent29;
+        30 => mhpmcounter30, mhpmevent30;
+        31 => mhpmcounter31, mhpmevent31;
+    }
+}
@@ -1,3 +1,8 @@
+use crate::{clint, console, hart_id, rfence, trap_stack, BOARD_INFO, GENESIS, NUM_HART_MAX};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 use rustsbi::{
     spec::srst::{
         RESET_REASON_NO_REASON, RESET_REASON_SYSTEM_FAILURE, RESET_TYPE_COLD_REBOOT,
@@ -12,6 +17,19 @@ pub(crate) struct QemuTest(usize);
 
 static TEST: Once<QemuTest> = Once::new();
 
+/// 按栈序号标记等待热重启的硬件线程。
+static WARM_REBOOT_PENDING: [AtomicBool; NUM_HART_MAX] =
+    [const { AtomicBool::new(false) }; NUM_HART_MAX];
+
+/// 按栈序号标记已完成初始化的硬件线程，不存在的硬件线程不会响应热重启。
+static ONLINE: [AtomicBool; NUM_HART_MAX] = [const { AtomicBool::new(false) }; NUM_HART_MAX];
+
+/// 尚未响应热重启的硬件线程数量。
+static WARM_REBOOT_WAITING: AtomicUsize = AtomicUsize::new(0);
+
+/// 全局初始化已清零 `.bss`，响应热重启的硬件线程等待它置位后才回到 `_start`。
+static GENESIS_STARTED: AtomicBool = AtomicBool::new(false);
+
 pub(crate) fn init(base: usize) {
     TEST.call_once(|| QemuTest(base));
 }
@@ -20,20 +38,107 @@ pub(crate) fn get() -> &'static QemuTest {
     TEST.wait()
 }
 
+/// 硬件线程完成初始化后调用。
+pub(crate) fn hart_online() {
+    if let Some(slot) = trap_stack::slot(hart_id()) {
+        ONLINE[slot].store(true, Ordering::Release);
+    }
+}
+
+/// 全局初始化清零 `.bss` 后调用，放行响应热重启的硬件线程。
+pub(crate) fn genesis_started() {
+    GENESIS_STARTED.store(true, Ordering::Release);
+}
+
 impl Reset for QemuTest {
     fn system_reset(&self, reset_type: u32, reset_reason: u32) -> SbiRet {
         let test = unsafe { &*(TEST.wait().0 as *const SifiveTestDevice) };
+        if matches!(
+            reset_type,
+            RESET_TYPE_SHUTDOWN | RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT
+        ) {
+            console::flush();
+        }
         match reset_type {
             RESET_TYPE_SHUTDOWN => match reset_reason {
                 RESET_REASON_NO_REASON => test.pass(),
                 RESET_REASON_SYSTEM_FAILURE => test.fail(-1 as _),
                 value => test.fail(value as _),
             },
-            RESET_TYPE_COLD_REBOOT | RESET_TYPE_WARM_REBOOT => {
-                // test.reset();
-                todo!()
-            }
+            RESET_TYPE_COLD_REBOOT => test.reset(),
+            RESET_TYPE_WARM_REBOOT => warm_reboot(),
             _ => SbiRet::invalid_param(),
         }
     }
 }
+
+/// 热重启。
+///
+/// 通知其他硬件线程回到 `_start`，等待它们全部响应后重置全局状态，
+/// 然后当前硬件线程重新执行全局初始化，并以原设备树启动特权软件。
+///
+/// 全局状态由新的全局初始化清零 `.bss` 重置，此前其他硬件线程仍可以访问板信息。
+fn warm_reboot() -> ! {
+    let hartid = hart_id();
+    let opaque = BOARD_INFO.wait().dtb.start;
+    GENESIS_STARTED.store(false, Ordering::Release);
+    let pending = || {
+        trap_stack::hart_ids().filter(move |&i| {
+            i != hartid && ONLINE[trap_stack::slot(i).unwrap()].load(Ordering::Acquire)
+        })
+    };
+    WARM_REBOOT_WAITING.store(pending().count(), Ordering::Release);
+    for i in pending() {
+        WARM_REBOOT_PENDING[trap_stack::slot(i).unwrap()].store(true, Ordering::Release);
+        clint::set_msip(i);
+    }
+    while WARM_REBOOT_WAITING.load(Ordering::Acquire) != 0 {
+        // 其他硬件线程响应前可能向此硬件线程发出远程同步请求
+        rfence::serve();
+        spin_loop();
+    }
+    // 所有硬件线程都已离开特权软件
+    trap_stack::reset_hsm();
+    GENESIS.store(1, Ordering::Release);
+    restart(hartid, opaque)
+}
+
+/// 检查当前硬件线程是否等待热重启，是则响应热重启，不再返回。
+///
+/// 在 machine soft 中断处理中调用。
+pub(crate) fn check_warm_reboot() {
+    if let Some(slot) = trap_stack::slot(hart_id()) {
+        if WARM_REBOOT_PENDING[slot].load(Ordering::Acquire) {
+            warm_reboot_ack()
+        }
+    }
+}
+
+/// 响应热重启。
+fn warm_reboot_ack() -> ! {
+    let hartid = hart_id();
+    clint::clear_msip();
+    WARM_REBOOT_PENDING[trap_stack::slot(hartid).unwrap()].store(false, Ordering::Release);
+    WARM_REBOOT_WAITING.fetch_sub(1, Ordering::AcqRel);
+    // 等待新的全局初始化开始，避免抢先认领全局初始化或使用旧的硬件线程表
+    while !GENESIS_STARTED.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    // 副核不使用设备树地址
+    restart(hartid, 0)
+}
+
+/// 清除特权软件的中断状态，从 `_start` 重新进入。
+fn restart(hartid: usize, opaque: usize) -> ! {
+    unsafe {
+        riscv::register::mip::clear_ssoft();
+        riscv::register::mip::clear_stimer();
+        core::arch::asm!(
+            "j {entry}",
+            entry = sym crate::_start,
+            in("a0") hartid,
+            in("a1") opaque,
+            options(noreturn),
+        )
+    }
+}
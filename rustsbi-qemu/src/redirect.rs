@@ -0,0 +1,45 @@
+use crate::{
+    riscv_spec::{mepc, mstatus},
+    unprivileged::TrapInfo,
+};
+use riscv::register::{scause, sepc, stval, stvec};
+
+/// 判断陷入是否来自 M 态。
+#[inline]
+pub(crate) fn from_machine() -> bool {
+    mstatus::read() & mstatus::MPP == mstatus::MPP_MACHINE
+}
+
+/// 将 S/U 态引起的异常转发给特权软件。
+///
+/// 模拟硬件委托异常的行为：设置 `scause`、`stval` 和 `sepc`，
+/// 按 `mstatus.MPP` 更新 `sstatus` 的 SPP、SPIE 和 SIE，然后从 `stvec` 继续执行。
+pub(crate) fn redirect(trap: TrapInfo) {
+    if from_machine() {
+        panic!(
+            "cannot redirect trap from machine mode: cause = {}, tval = {:#x}, mepc = {:#x}",
+            trap.cause,
+            trap.tval,
+            mepc::read(),
+        );
+    }
+    unsafe {
+        scause::write(trap.cause);
+        stval::write(trap.tval);
+    }
+    sepc::write(mepc::read());
+    mstatus::update(|bits| {
+        let from_supervisor = *bits & mstatus::MPP == mstatus::MPP_SUPERVISOR;
+        let sie = *bits & mstatus::SIE != 0;
+        *bits &= !(mstatus::SPP | mstatus::SPIE | mstatus::SIE | mstatus::MPP);
+        if from_supervisor {
+            *bits |= mstatus::SPP;
+        }
+        if sie {
+            *bits |= mstatus::SPIE;
+        }
+        *bits |= mstatus::MPP_SUPERVISOR;
+    });
+    // 异常总是进入 stvec 基地址
+    mepc::write(stvec::read().address());
+}
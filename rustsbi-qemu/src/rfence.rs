@@ -0,0 +1,387 @@
+use crate::{
+    clint, hart_id, pmu,
+    trap_stack::{local_mailbox, remote_hsm, remote_mailbox, select_harts},
+};
+use core::{
+    arch::asm,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use riscv::register::misa;
+use rustsbi::{HartMask, SbiRet};
+use sbi_spec::pmu::firmware_event;
+use spin::Mutex;
+
+pub(crate) struct RFence;
+
+impl rustsbi::Fence for RFence {
+    #[inline]
+    fn remote_fence_i(&self, hart_mask: HartMask) -> SbiRet {
+        remote(hart_mask, Fence::I)
+    }
+
+    #[inline]
+    fn remote_sfence_vma(&self, hart_mask: HartMask, start_addr: usize, size: usize) -> SbiRet {
+        remote(hart_mask, Fence::Vma { start_addr, size })
+    }
+
+    #[inline]
+    fn remote_sfence_vma_asid(
+        &self,
+        hart_mask: HartMask,
+        start_addr: usize,
+        size: usize,
+        asid: usize,
+    ) -> SbiRet {
+        remote(
+            hart_mask,
+            Fence::VmaAsid {
+                start_addr,
+                size,
+                asid,
+            },
+        )
+    }
+
+    #[inline]
+    fn remote_hfence_gvma_vmid(
+        &self,
+        hart_mask: HartMask,
+        start_addr: usize,
+        size: usize,
+        vmid: usize,
+    ) -> SbiRet {
+        if !has_hypervisor() {
+            return SbiRet::not_supported();
+        }
+        remote(
+            hart_mask,
+            Fence::GvmaVmid {
+                start_addr,
+                size,
+                vmid,
+            },
+        )
+    }
+
+    #[inline]
+    fn remote_hfence_gvma(&self, hart_mask: HartMask, start_addr: usize, size: usize) -> SbiRet {
+        if !has_hypervisor() {
+            return SbiRet::not_supported();
+        }
+        remote(hart_mask, Fence::Gvma { start_addr, size })
+    }
+
+    #[inline]
+    fn remote_hfence_vvma_asid(
+        &self,
+        hart_mask: HartMask,
+        start_addr: usize,
+        size: usize,
+        asid: usize,
+    ) -> SbiRet {
+        if !has_hypervisor() {
+            return SbiRet::not_supported();
+        }
+        remote(
+            hart_mask,
+            Fence::VvmaAsid {
+                start_addr,
+                size,
+                asid,
+                vmid: current_vmid(),
+            },
+        )
+    }
+
+    #[inline]
+    fn remote_hfence_vvma(&self, hart_mask: HartMask, start_addr: usize, size: usize) -> SbiRet {
+        if !has_hypervisor() {
+            return SbiRet::not_supported();
+        }
+        remote(
+            hart_mask,
+            Fence::Vvma {
+                start_addr,
+                size,
+                vmid: current_vmid(),
+            },
+        )
+    }
+}
+
+/// 此 hart 是否实现了 H 扩展。
+#[inline]
+fn has_hypervisor() -> bool {
+    misa::read().map_or(false, |isa| isa.has_extension('H'))
+}
+
+/// `hgatp` 中 VMID 字段的位置。
+const HGATP_VMID_SHIFT: usize = 44;
+const HGATP_VMID_MASK: usize = ((1 << 14) - 1) << HGATP_VMID_SHIFT;
+
+/// 调用方当前虚拟机的 VMID。
+#[inline]
+fn current_vmid() -> usize {
+    let hgatp: usize;
+    unsafe { asm!("csrr {}, 0x680", out(reg) hgatp) };
+    (hgatp & HGATP_VMID_MASK) >> HGATP_VMID_SHIFT
+}
+
+/// 临时切换到 `vmid` 指定的虚拟机执行 `f`。
+///
+/// `hfence.vvma` 作用于 `hgatp` 中的当前虚拟机，远程执行时要换成调用方的虚拟机。
+fn with_vmid(vmid: usize, f: impl FnOnce()) {
+    let hgatp: usize;
+    unsafe {
+        asm!("csrr {}, 0x680", out(reg) hgatp);
+        asm!("csrw 0x680, {}", in(reg) (hgatp & !HGATP_VMID_MASK) | (vmid << HGATP_VMID_SHIFT));
+    }
+    f();
+    unsafe { asm!("csrw 0x680, {}", in(reg) hgatp) };
+}
+
+/// 远程同步操作。
+#[derive(Clone, Copy)]
+enum Fence {
+    I,
+    Vma {
+        start_addr: usize,
+        size: usize,
+    },
+    VmaAsid {
+        start_addr: usize,
+        size: usize,
+        asid: usize,
+    },
+    Gvma {
+        start_addr: usize,
+        size: usize,
+    },
+    GvmaVmid {
+        start_addr: usize,
+        size: usize,
+        vmid: usize,
+    },
+    Vvma {
+        start_addr: usize,
+        size: usize,
+        vmid: usize,
+    },
+    VvmaAsid {
+        start_addr: usize,
+        size: usize,
+        asid: usize,
+        vmid: usize,
+    },
+}
+
+impl Fence {
+    /// 超过这个长度的地址范围将刷新整个地址空间。
+    const LEN_FLUSH_LIMIT: usize = 64 << 12;
+
+    /// 在此 hart 上执行。
+    ///
+    /// 汇编器未启用 H 扩展，`hfence.vvma` 和 `hfence.gvma` 以 `.insn` 编码。
+    /// `hfence.gvma` 的地址操作数是客户机物理地址右移 2 位。
+    fn execute(self) {
+        match self {
+            Self::I => unsafe { asm!("fence.i") },
+            Self::Vma { start_addr, size } => match pages(start_addr, size) {
+                Some(pages) => {
+                    pages.for_each(|addr| unsafe { asm!("sfence.vma {}", in(reg) addr) })
+                }
+                None => unsafe { asm!("sfence.vma") },
+            },
+            Self::VmaAsid {
+                start_addr,
+                size,
+                asid,
+            } => match pages(start_addr, size) {
+                Some(pages) => pages.for_each(|addr| unsafe {
+                    asm!("sfence.vma {}, {}", in(reg) addr, in(reg) asid)
+                }),
+                None => unsafe { asm!("sfence.vma zero, {}", in(reg) asid) },
+            },
+            Self::Gvma { start_addr, size } => match pages(start_addr, size) {
+                Some(pages) => pages.for_each(|gpa| unsafe {
+                    asm!(".insn r 0x73, 0, 0x31, zero, {}, zero", in(reg) gpa >> 2)
+                }),
+                None => unsafe { asm!(".insn r 0x73, 0, 0x31, zero, zero, zero") },
+            },
+            Self::GvmaVmid {
+                start_addr,
+                size,
+                vmid,
+            } => match pages(start_addr, size) {
+                Some(pages) => pages.for_each(|gpa| unsafe {
+                    asm!(".insn r 0x73, 0, 0x31, zero, {}, {}", in(reg) gpa >> 2, in(reg) vmid)
+                }),
+                None => unsafe { asm!(".insn r 0x73, 0, 0x31, zero, zero, {}", in(reg) vmid) },
+            },
+            Self::Vvma {
+                start_addr,
+                size,
+                vmid,
+            } => with_vmid(vmid, || match pages(start_addr, size) {
+                Some(pages) => pages.for_each(|addr| unsafe {
+                    asm!(".insn r 0x73, 0, 0x11, zero, {}, zero", in(reg) addr)
+                }),
+                None => unsafe { asm!(".insn r 0x73, 0, 0x11, zero, zero, zero") },
+            }),
+            Self::VvmaAsid {
+                start_addr,
+                size,
+                asid,
+                vmid,
+            } => with_vmid(vmid, || match pages(start_addr, size) {
+                Some(pages) => pages.for_each(|addr| unsafe {
+                    asm!(".insn r 0x73, 0, 0x11, zero, {}, {}", in(reg) addr, in(reg) asid)
+                }),
+                None => unsafe { asm!(".insn r 0x73, 0, 0x11, zero, zero, {}", in(reg) asid) },
+            }),
+        }
+    }
+
+    /// 发送和接收这个操作对应的固件事件。
+    fn events(self) -> (usize, usize) {
+        use firmware_event::*;
+        match self {
+            Self::I => (FENCE_I_SENT, FENCE_I_RECEIVED),
+            Self::Vma { .. } => (SFENCE_VMA_SENT, SFENCE_VMA_RECEIVED),
+            Self::VmaAsid { .. } => (SFENCE_VMA_ASID_SENT, SFENCE_VMA_ASID_RECEIVED),
+            Self::Gvma { .. } => (HFENCE_GVMA_SENT, HFENCE_GVMA_RECEIVED),
+            Self::GvmaVmid { .. } => (HFENCE_GVMA_VMID_SENT, HFENCE_GVMA_VMID_RECEIVED),
+            Self::Vvma { .. } => (HFENCE_VVMA_SENT, HFENCE_VVMA_RECEIVED),
+            Self::VvmaAsid { .. } => (HFENCE_VVMA_ASID_SENT, HFENCE_VVMA_ASID_RECEIVED),
+        }
+    }
+}
+
+/// 要逐页刷新的地址，需要刷新整个地址空间时返回 `None`。
+fn pages(start_addr: usize, size: usize) -> Option<impl Iterator<Item = usize>> {
+    const PAGE: usize = 1 << 12;
+    let full =
+        (start_addr == 0 && size == 0) || size == usize::MAX || size > Fence::LEN_FLUSH_LIMIT;
+    let end = start_addr.checked_add(size).filter(|_| !full)?;
+    Some((start_addr & !(PAGE - 1)..end).step_by(PAGE))
+}
+
+/// 邮箱中的远程同步请求。
+#[derive(Clone, Copy)]
+struct Request {
+    fence: Fence,
+    /// 发送方等待完成的计数器，发送方在计数归零前不会返回。
+    pending: *const AtomicUsize,
+}
+
+/// 每个 hart 接收核间消息的邮箱。
+pub(crate) struct Mailbox {
+    /// 特权软件核间中断。
+    ipi: AtomicBool,
+    /// 远程同步请求队列。
+    queue: Mutex<Queue>,
+}
+
+/// 远程同步请求环形队列。
+struct Queue {
+    head: usize,
+    len: usize,
+    items: [Option<Request>; Queue::CAPACITY],
+}
+
+impl Queue {
+    const CAPACITY: usize = 8;
+
+    fn push(&mut self, req: Request) -> bool {
+        if self.len < Self::CAPACITY {
+            self.items[(self.head + self.len) % Self::CAPACITY] = Some(req);
+            self.len += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn pop(&mut self) -> Option<Request> {
+        if self.len > 0 {
+            let req = self.items[self.head].take();
+            self.head = (self.head + 1) % Self::CAPACITY;
+            self.len -= 1;
+            req
+        } else {
+            None
+        }
+    }
+}
+
+impl Mailbox {
+    pub const fn new() -> Self {
+        Self {
+            ipi: AtomicBool::new(false),
+            queue: Mutex::new(Queue {
+                head: 0,
+                len: 0,
+                items: [None; Queue::CAPACITY],
+            }),
+        }
+    }
+
+    /// 标记特权软件核间中断。
+    #[inline]
+    pub fn post_ipi(&self) {
+        self.ipi.store(true, Ordering::Release);
+    }
+
+    /// 取出特权软件核间中断标记。
+    #[inline]
+    pub fn take_ipi(&self) -> bool {
+        self.ipi.swap(false, Ordering::AcqRel)
+    }
+}
+
+/// 处理此 hart 邮箱中的所有远程同步请求。
+pub(crate) fn serve() {
+    let mailbox = local_mailbox();
+    while let Some(req) = mailbox.queue.lock().pop() {
+        req.fence.execute();
+        pmu::record(req.fence.events().1);
+        unsafe { &*req.pending }.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// 向 `hart_mask` 指定的 hart 发送远程同步请求，并等待它们全部完成。
+fn remote(hart_mask: HartMask, fence: Fence) -> SbiRet {
+    let Some(harts) = select_harts(hart_mask) else {
+        return SbiRet::invalid_param();
+    };
+    let hartid = hart_id();
+    let pending = AtomicUsize::new(0);
+    for i in harts {
+        if i == hartid {
+            fence.execute();
+            continue;
+        }
+        if !remote_hsm(i).map_or(false, |hsm| hsm.allow_ipi()) {
+            continue;
+        }
+        let mailbox = remote_mailbox(i).unwrap();
+        pending.fetch_add(1, Ordering::Relaxed);
+        let req = Request {
+            fence,
+            pending: &pending,
+        };
+        // 对方队列已满时处理自己的请求，避免互相等待
+        while !mailbox.queue.lock().push(req) {
+            serve();
+            spin_loop();
+        }
+        clint::set_msip(i);
+        pmu::record(fence.events().0);
+    }
+    while pending.load(Ordering::Acquire) != 0 {
+        serve();
+        spin_loop();
+    }
+    SbiRet::success(0)
+}
@@ -14,6 +14,13 @@ pub mod mie {
     pub const MEIE: usize = 1 << 11;
     pub const SGEIE: usize = 1 << 12;
 
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, mie", out(reg) bits, options(nomem)) };
+        bits
+    }
+
     #[inline(always)]
     pub fn write(bits: usize) {
         unsafe { asm!("csrw mie, {}", in(reg) bits, options(nomem)) };
@@ -92,3 +99,164 @@ pub mod mepc {
         unsafe { asm!("csrw mepc, {}", in(reg) bits, options(nomem)) };
     }
 }
+
+pub mod mcounteren {
+    use core::arch::asm;
+
+    pub const CY: u32 = 1 << 0;
+    pub const TM: u32 = 1 << 1;
+    pub const IR: u32 = 1 << 2;
+
+    #[inline(always)]
+    pub fn write(bits: u32) {
+        unsafe { asm!("csrw mcounteren, {}", in(reg) bits as usize, options(nomem)) };
+    }
+}
+
+pub mod mcountinhibit {
+    use core::arch::asm;
+
+    pub const CY: u32 = 1 << 0;
+    pub const IR: u32 = 1 << 2;
+
+    #[inline(always)]
+    pub fn read() -> u32 {
+        let bits: usize;
+        unsafe { asm!("csrr {}, mcountinhibit", out(reg) bits, options(nomem)) };
+        bits as _
+    }
+
+    #[inline(always)]
+    pub fn write(bits: u32) {
+        unsafe { asm!("csrw mcountinhibit, {}", in(reg) bits as usize, options(nomem)) };
+    }
+
+    #[inline(always)]
+    pub fn set(bits: u32) {
+        unsafe { asm!("csrs mcountinhibit, {}", in(reg) bits as usize, options(nomem)) };
+    }
+
+    #[inline(always)]
+    pub fn clear(bits: u32) {
+        unsafe { asm!("csrc mcountinhibit, {}", in(reg) bits as usize, options(nomem)) };
+    }
+}
+
+pub mod mcycle {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn write(bits: u64) {
+        unsafe { asm!("csrw mcycle, {}", in(reg) bits as usize, options(nomem)) };
+    }
+}
+
+pub mod minstret {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn write(bits: u64) {
+        unsafe { asm!("csrw minstret, {}", in(reg) bits as usize, options(nomem)) };
+    }
+}
+
+pub mod menvcfg {
+    use core::arch::asm;
+
+    pub const PMM: usize = 3 << 32;
+    pub const ADUE: usize = 1 << 61;
+    pub const STCE: usize = 1 << 63;
+
+    pub const PMM_PMLEN_7: usize = 2 << 32;
+    pub const PMM_PMLEN_16: usize = 3 << 32;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, menvcfg", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn set(bits: usize) {
+        unsafe { asm!("csrs menvcfg, {}", in(reg) bits, options(nomem)) };
+    }
+
+    #[inline(always)]
+    pub fn clear(bits: usize) {
+        unsafe { asm!("csrc menvcfg, {}", in(reg) bits, options(nomem)) };
+    }
+}
+
+pub mod stimecmp {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn write(bits: u64) {
+        unsafe { asm!("csrw stimecmp, {}", in(reg) bits as usize, options(nomem)) };
+    }
+}
+
+pub mod tselect {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, tselect", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw tselect, {}", in(reg) bits, options(nomem)) };
+    }
+}
+
+pub mod tdata1 {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, tdata1", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw tdata1, {}", in(reg) bits, options(nomem)) };
+    }
+}
+
+pub mod tdata2 {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, tdata2", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw tdata2, {}", in(reg) bits, options(nomem)) };
+    }
+}
+
+pub mod tdata3 {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let bits: usize;
+        unsafe { asm!("csrr {}, tdata3", out(reg) bits, options(nomem)) };
+        bits
+    }
+
+    #[inline(always)]
+    pub fn write(bits: usize) {
+        unsafe { asm!("csrw tdata3, {}", in(reg) bits, options(nomem)) };
+    }
+}
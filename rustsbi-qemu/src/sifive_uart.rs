@@ -0,0 +1,72 @@
+use crate::console::ConsoleDevice;
+use spin::Once;
+
+static INSTANCE: Once<SifiveUart> = Once::new();
+
+pub(crate) fn init(base: usize) -> &'static dyn ConsoleDevice {
+    INSTANCE.call_once(|| {
+        let uart = SifiveUart(base);
+        unsafe {
+            // 发送队列空时产生发送中断
+            uart.reg(TXCTRL)
+                .write_volatile(TXCTRL_TXEN | 1 << TXCTRL_TXCNT_SHIFT);
+            uart.reg(RXCTRL).write_volatile(RXCTRL_RXEN);
+        }
+        uart
+    })
+}
+
+/// 寄存器偏移。
+const TXDATA: usize = 0x00;
+const RXDATA: usize = 0x04;
+const TXCTRL: usize = 0x08;
+const RXCTRL: usize = 0x0c;
+const IE: usize = 0x10;
+
+/// 发送队列满或接收队列空。
+const DATA_FULL_EMPTY: u32 = 1 << 31;
+const TXCTRL_TXEN: u32 = 1 << 0;
+const TXCTRL_TXCNT_SHIFT: u32 = 16;
+const RXCTRL_RXEN: u32 = 1 << 0;
+const IE_TXWM: u32 = 1 << 0;
+const IE_RXWM: u32 = 1 << 1;
+
+/// SiFive UART0。
+pub(crate) struct SifiveUart(usize);
+
+impl SifiveUart {
+    #[inline]
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.0 + offset) as _
+    }
+}
+
+impl ConsoleDevice for SifiveUart {
+    #[inline]
+    fn receive(&self) -> Option<u8> {
+        let rxdata = unsafe { self.reg(RXDATA).read_volatile() };
+        (rxdata & DATA_FULL_EMPTY == 0).then_some(rxdata as u8)
+    }
+
+    #[inline]
+    fn transmit(&self, c: u8) -> bool {
+        // 写入 txdata 前必须确认队列未满，队列满时写入的字符被丢弃
+        let txdata = self.reg(TXDATA);
+        if unsafe { txdata.read_volatile() } & DATA_FULL_EMPTY != 0 {
+            return false;
+        }
+        unsafe { txdata.write_volatile(c as _) };
+        true
+    }
+
+    fn set_interrupts(&self, rx: bool, tx: bool) {
+        let mut ie = 0;
+        if rx {
+            ie |= IE_RXWM;
+        }
+        if tx {
+            ie |= IE_TXWM;
+        }
+        unsafe { self.reg(IE).write_volatile(ie) };
+    }
+}
@@ -0,0 +1,462 @@
+use crate::{
+    clint, hart_id,
+    riscv_spec::{mepc, mstatus},
+    supervisor_accessible,
+    trap_stack::{local_sse, remote_hsm, remote_sse},
+};
+use riscv::register::sepc;
+use rustsbi::SbiRet;
+use spin::Mutex;
+
+/// 特权态软件事件扩展号 "SSE"。
+///
+/// 支持软件注入的本地事件和全局事件，事件在返回特权软件时投递，每个 hart 同一时间只处理一个事件。
+pub(crate) const EID_SSE: usize = 0x53_5345;
+const READ_ATTRS: usize = 0;
+const WRITE_ATTRS: usize = 1;
+const REGISTER: usize = 2;
+const UNREGISTER: usize = 3;
+const ENABLE: usize = 4;
+const DISABLE: usize = 5;
+/// 事件处理完成，不返回调用者而是回到被打断的位置。
+pub(crate) const COMPLETE: usize = 6;
+const INJECT: usize = 7;
+const HART_UNMASK: usize = 8;
+const HART_MASK: usize = 9;
+
+/// 软件注入的本地事件和全局事件。
+const LOCAL_SOFTWARE_INJECTED: u32 = 0xffff_0000;
+const GLOBAL_SOFTWARE_INJECTED: u32 = 0xffff_8000;
+/// 规范定义但平台没有事件源的事件。
+const UNSUPPORTED_EVENTS: [u32; 6] = [0, 1, 0x8000, 0x1_0000, 0x10_0000, 0x10_8000];
+
+/// 事件属性编号。
+mod attr {
+    pub const STATUS: usize = 0;
+    pub const PRIORITY: usize = 1;
+    pub const CONFIG: usize = 2;
+    pub const PREFERRED_HART: usize = 3;
+    pub const ENTRY_PC: usize = 4;
+    pub const ENTRY_ARG: usize = 5;
+    pub const INTERRUPTED_SEPC: usize = 6;
+    pub const INTERRUPTED_FLAGS: usize = 7;
+    pub const INTERRUPTED_A6: usize = 8;
+    pub const INTERRUPTED_A7: usize = 9;
+    /// 属性数量。
+    pub const NUM: usize = 10;
+}
+
+/// 事件状态。
+mod state {
+    pub const UNUSED: usize = 0;
+    pub const REGISTERED: usize = 1;
+    pub const ENABLED: usize = 2;
+    pub const RUNNING: usize = 3;
+}
+
+const STATUS_PENDING: usize = 1 << 2;
+const STATUS_INJECT: usize = 1 << 3;
+const CONFIG_ONESHOT: usize = 1 << 0;
+const FLAG_SPP: usize = 1 << 0;
+const FLAG_SPIE: usize = 1 << 1;
+
+/// SBI v3.0 定义的 `SBI_ERR_INVALID_STATE` 和 `SBI_ERR_BAD_RANGE`。
+const ERR_INVALID_STATE: usize = -10isize as usize;
+const ERR_BAD_RANGE: usize = -11isize as usize;
+
+#[inline]
+const fn error(error: usize) -> SbiRet {
+    SbiRet { error, value: 0 }
+}
+
+/// 全局事件默认投递给 `boot_hart`。
+pub(crate) fn init(boot_hart: usize) {
+    GLOBAL.lock().preferred_hart = boot_hart;
+}
+
+/// 全局事件。
+static GLOBAL: Mutex<Event> = Mutex::new(Event::new());
+
+/// 一个事件的状态和属性。
+struct Event {
+    state: usize,
+    pending: bool,
+    priority: usize,
+    config: usize,
+    preferred_hart: usize,
+    entry_pc: usize,
+    entry_arg: usize,
+    /// 被打断的 sepc、标志、a6 和 a7。
+    interrupted: [usize; 4],
+}
+
+impl Event {
+    const fn new() -> Self {
+        Self {
+            state: state::UNUSED,
+            pending: false,
+            priority: 0,
+            config: 0,
+            preferred_hart: 0,
+            entry_pc: 0,
+            entry_arg: 0,
+            interrupted: [0; 4],
+        }
+    }
+
+    #[inline]
+    fn ready(&self) -> bool {
+        self.state == state::ENABLED && self.pending
+    }
+
+    fn read(&self, id: usize, global: bool) -> usize {
+        use attr::*;
+        match id {
+            STATUS => self.state | STATUS_INJECT | if self.pending { STATUS_PENDING } else { 0 },
+            PRIORITY => self.priority,
+            CONFIG => self.config,
+            PREFERRED_HART if global => self.preferred_hart,
+            PREFERRED_HART => hart_id(),
+            ENTRY_PC => self.entry_pc,
+            ENTRY_ARG => self.entry_arg,
+            _ => self.interrupted[id - INTERRUPTED_SEPC],
+        }
+    }
+
+    /// 检查属性是否可以写入 `value`。
+    fn check_write(&self, id: usize, value: usize, global: bool) -> Result<(), SbiRet> {
+        use attr::*;
+        let writable = match id {
+            PRIORITY | CONFIG => true,
+            PREFERRED_HART => global,
+            INTERRUPTED_SEPC | INTERRUPTED_FLAGS | INTERRUPTED_A6 | INTERRUPTED_A7 => true,
+            _ => false,
+        };
+        if !writable {
+            return Err(error(ERR_BAD_RANGE));
+        }
+        let (state_ok, value_ok) = match id {
+            PRIORITY => (self.state < state::ENABLED, value <= u32::MAX as usize),
+            CONFIG => (self.state < state::ENABLED, value & !CONFIG_ONESHOT == 0),
+            PREFERRED_HART => (self.state < state::ENABLED, remote_sse(value).is_some()),
+            INTERRUPTED_FLAGS => (
+                self.state == state::RUNNING,
+                value & !(FLAG_SPP | FLAG_SPIE) == 0,
+            ),
+            _ => (self.state == state::RUNNING, true),
+        };
+        if !state_ok {
+            Err(error(ERR_INVALID_STATE))
+        } else if !value_ok {
+            Err(SbiRet::invalid_param())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn write(&mut self, id: usize, value: usize) {
+        use attr::*;
+        match id {
+            PRIORITY => self.priority = value,
+            CONFIG => self.config = value,
+            PREFERRED_HART => self.preferred_hart = value,
+            _ => self.interrupted[id - INTERRUPTED_SEPC] = value,
+        }
+    }
+
+    /// 保存被打断的特权软件状态，并转到事件处理入口。
+    fn enter(&mut self, regs: &mut [usize; 8]) {
+        let status = mstatus::read();
+        let mut flags = 0;
+        if status & mstatus::SPP != 0 {
+            flags |= FLAG_SPP;
+        }
+        if status & mstatus::SPIE != 0 {
+            flags |= FLAG_SPIE;
+        }
+        self.interrupted = [sepc::read(), flags, regs[6], regs[7]];
+        sepc::write(mepc::read());
+        mstatus::update(|bits| {
+            let from_supervisor = *bits & mstatus::MPP == mstatus::MPP_SUPERVISOR;
+            let sie = *bits & mstatus::SIE != 0;
+            *bits &= !(mstatus::MPP | mstatus::SPP | mstatus::SPIE | mstatus::SIE);
+            *bits |= mstatus::MPP_SUPERVISOR;
+            if from_supervisor {
+                *bits |= mstatus::SPP;
+            }
+            if sie {
+                *bits |= mstatus::SPIE;
+            }
+        });
+        regs[6] = hart_id();
+        regs[7] = self.entry_arg;
+        mepc::write(self.entry_pc);
+        self.state = state::RUNNING;
+        self.pending = false;
+    }
+
+    /// 从事件处理返回被打断的位置，恢复保存的特权软件状态。
+    fn leave(&mut self, regs: &mut [usize; 8]) {
+        let [interrupted_sepc, flags, a6, a7] = self.interrupted;
+        mepc::write(sepc::read());
+        mstatus::update(|bits| {
+            let spp = *bits & mstatus::SPP != 0;
+            let spie = *bits & mstatus::SPIE != 0;
+            *bits &= !(mstatus::MPP | mstatus::SPP | mstatus::SPIE | mstatus::SIE);
+            *bits |= if spp {
+                mstatus::MPP_SUPERVISOR
+            } else {
+                mstatus::MPP_USER
+            };
+            if spie {
+                *bits |= mstatus::SIE;
+            }
+            if flags & FLAG_SPP != 0 {
+                *bits |= mstatus::SPP;
+            }
+            if flags & FLAG_SPIE != 0 {
+                *bits |= mstatus::SPIE;
+            }
+        });
+        sepc::write(interrupted_sepc);
+        regs[6] = a6;
+        regs[7] = a7;
+        self.state = if self.config & CONFIG_ONESHOT != 0 {
+            state::REGISTERED
+        } else {
+            state::ENABLED
+        };
+    }
+}
+
+/// 每个 hart 的软件事件状态。
+pub(crate) struct SseState(Mutex<Local>);
+
+struct Local {
+    event: Event,
+    /// 此 hart 屏蔽所有事件。
+    masked: bool,
+    /// 正在处理的事件。
+    running: Option<u32>,
+}
+
+impl SseState {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Local {
+            event: Event::new(),
+            masked: true,
+            running: None,
+        }))
+    }
+}
+
+/// 将此 hart 的软件事件状态恢复为初始状态。
+///
+/// 特权软件启动时调用。
+pub(crate) fn reset() {
+    *local_sse().0.lock() = SseState::new().0.into_inner();
+}
+
+/// 对 `event_id` 指定的事件执行 `f`，`f` 的第二个参数表示是否全局事件。
+fn with_event<T>(event_id: usize, f: impl FnOnce(&mut Event, bool) -> T) -> Result<T, SbiRet> {
+    match u32::try_from(event_id) {
+        Ok(LOCAL_SOFTWARE_INJECTED) => Ok(f(&mut local_sse().0.lock().event, false)),
+        Ok(GLOBAL_SOFTWARE_INJECTED) => Ok(f(&mut GLOBAL.lock(), true)),
+        Ok(id) if UNSUPPORTED_EVENTS.contains(&id) => Err(SbiRet::not_supported()),
+        _ => Err(SbiRet::invalid_param()),
+    }
+}
+
+/// 处理软件事件扩展中除 [`COMPLETE`] 之外的调用。
+pub(crate) fn handle(fid: usize, [a0, a1, a2, a3, a4, _]: [usize; 6]) -> SbiRet {
+    match fid {
+        READ_ATTRS => read_attrs(a0, a1, a2, a3, a4),
+        WRITE_ATTRS => write_attrs(a0, a1, a2, a3, a4),
+        REGISTER => register(a0, a1, a2),
+        UNREGISTER => transition(a0, |s| {
+            matches!(s, state::REGISTERED | state::ENABLED).then_some(state::UNUSED)
+        }),
+        ENABLE => transition(a0, |s| (s == state::REGISTERED).then_some(state::ENABLED)),
+        DISABLE => transition(a0, |s| (s == state::ENABLED).then_some(state::REGISTERED)),
+        INJECT => inject(a0, a1),
+        HART_UNMASK => set_masked(false),
+        HART_MASK => set_masked(true),
+        _ => SbiRet::not_supported(),
+    }
+}
+
+/// 检查属性缓冲区，返回缓冲区和属性编号范围。
+fn attrs_buffer(
+    base: usize,
+    count: usize,
+    lo: usize,
+    hi: usize,
+) -> Result<(&'static mut [usize], usize), SbiRet> {
+    if count == 0 {
+        return Err(SbiRet::invalid_param());
+    }
+    if base.checked_add(count).map_or(true, |end| end > attr::NUM) {
+        return Err(error(ERR_BAD_RANGE));
+    }
+    let len = count * core::mem::size_of::<usize>();
+    let valid =
+        hi == 0 && lo % core::mem::size_of::<usize>() == 0 && supervisor_accessible(lo, len);
+    if valid {
+        Ok((
+            unsafe { core::slice::from_raw_parts_mut(lo as *mut usize, count) },
+            base,
+        ))
+    } else {
+        Err(SbiRet::invalid_address())
+    }
+}
+
+fn read_attrs(event_id: usize, base: usize, count: usize, lo: usize, hi: usize) -> SbiRet {
+    let result = with_event(event_id, |event, global| {
+        let (buf, base) = attrs_buffer(base, count, lo, hi)?;
+        for (i, value) in buf.iter_mut().enumerate() {
+            *value = event.read(base + i, global);
+        }
+        Ok(())
+    });
+    match result {
+        Ok(Ok(())) => SbiRet::success(0),
+        Ok(Err(e)) | Err(e) => e,
+    }
+}
+
+fn write_attrs(event_id: usize, base: usize, count: usize, lo: usize, hi: usize) -> SbiRet {
+    let result = with_event(event_id, |event, global| {
+        let (buf, base) = attrs_buffer(base, count, lo, hi)?;
+        // 全部检查通过才写入
+        for (i, value) in buf.iter().enumerate() {
+            event.check_write(base + i, *value, global)?;
+        }
+        for (i, value) in buf.iter().enumerate() {
+            event.write(base + i, *value);
+        }
+        Ok(())
+    });
+    match result {
+        Ok(Ok(())) => SbiRet::success(0),
+        Ok(Err(e)) | Err(e) => e,
+    }
+}
+
+fn register(event_id: usize, entry_pc: usize, entry_arg: usize) -> SbiRet {
+    // 入口可能是虚拟地址，只检查对齐
+    if entry_pc % 2 != 0 {
+        return SbiRet::invalid_address();
+    }
+    let result = with_event(event_id, |event, _| {
+        if event.state != state::UNUSED {
+            return error(ERR_INVALID_STATE);
+        }
+        event.entry_pc = entry_pc;
+        event.entry_arg = entry_arg;
+        event.state = state::REGISTERED;
+        SbiRet::success(0)
+    });
+    result.unwrap_or_else(|e| e)
+}
+
+/// 按 `f` 转换事件状态，`f` 返回 `None` 表示当前状态不允许这个操作。
+fn transition(event_id: usize, f: impl FnOnce(usize) -> Option<usize>) -> SbiRet {
+    let result = with_event(event_id, |event, _| match f(event.state) {
+        Some(state) => {
+            event.state = state;
+            if state == state::UNUSED {
+                event.pending = false;
+            }
+            SbiRet::success(0)
+        }
+        None => error(ERR_INVALID_STATE),
+    });
+    result.unwrap_or_else(|e| e)
+}
+
+fn inject(event_id: usize, hart_id: usize) -> SbiRet {
+    let target = match u32::try_from(event_id) {
+        Ok(LOCAL_SOFTWARE_INJECTED) => match remote_sse(hart_id) {
+            Some(sse) => {
+                sse.0.lock().event.pending = true;
+                hart_id
+            }
+            None => return SbiRet::invalid_param(),
+        },
+        Ok(GLOBAL_SOFTWARE_INJECTED) => {
+            let mut event = GLOBAL.lock();
+            event.pending = true;
+            event.preferred_hart
+        }
+        Ok(id) if UNSUPPORTED_EVENTS.contains(&id) => return SbiRet::not_supported(),
+        _ => return SbiRet::invalid_param(),
+    };
+    // 注入给自己的事件在返回时投递，其他 hart 收到核间中断时投递
+    if target != crate::hart_id() && remote_hsm(target).map_or(false, |hsm| hsm.allow_ipi()) {
+        clint::set_msip(target);
+    }
+    SbiRet::success(0)
+}
+
+fn set_masked(masked: bool) -> SbiRet {
+    let mut local = local_sse().0.lock();
+    if local.masked == masked {
+        if masked {
+            SbiRet::already_stopped()
+        } else {
+            SbiRet::already_started()
+        }
+    } else {
+        local.masked = masked;
+        SbiRet::success(0)
+    }
+}
+
+/// 此 hart 是否有可以投递的事件，挂起的 hart 因此唤醒。
+pub(crate) fn pending() -> bool {
+    let local = local_sse().0.lock();
+    if local.masked || local.running.is_some() {
+        return false;
+    }
+    let global = GLOBAL.lock();
+    local.event.ready() || (global.ready() && global.preferred_hart == hart_id())
+}
+
+/// 返回特权软件前检查并投递事件。
+///
+/// `regs` 是将要写回的 a0 到 a7。同一时间每个 hart 只处理一个事件，优先级数值小的事件先投递。
+pub(crate) fn deliver(regs: &mut [usize; 8]) {
+    let mut local = local_sse().0.lock();
+    if local.masked || local.running.is_some() {
+        return;
+    }
+    let mut global = GLOBAL.lock();
+    let local_ready = local.event.ready();
+    let global_ready = global.ready() && global.preferred_hart == hart_id();
+    let id = match (local_ready, global_ready) {
+        (true, true) if global.priority < local.event.priority => GLOBAL_SOFTWARE_INJECTED,
+        (true, _) => LOCAL_SOFTWARE_INJECTED,
+        (false, true) => GLOBAL_SOFTWARE_INJECTED,
+        (false, false) => return,
+    };
+    if id == LOCAL_SOFTWARE_INJECTED {
+        local.event.enter(regs);
+    } else {
+        global.enter(regs);
+    }
+    local.running = Some(id);
+}
+
+/// 完成正在处理的事件，回到被打断的位置。
+///
+/// 此 hart 没有正在处理的事件时返回错误，这时调用者应正常返回。
+pub(crate) fn complete(regs: &mut [usize; 8]) -> Result<(), SbiRet> {
+    let mut local = local_sse().0.lock();
+    match local.running.take() {
+        Some(LOCAL_SOFTWARE_INJECTED) => local.event.leave(regs),
+        Some(_) => GLOBAL.lock().leave(regs),
+        None => return Err(error(ERR_INVALID_STATE)),
+    }
+    Ok(())
+}
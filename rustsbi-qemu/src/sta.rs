@@ -0,0 +1,102 @@
+use crate::{
+    clint, constants::NUM_MEMORY_REGION_MAX, device_tree::RangeList, trap_stack::local_sta,
+};
+use core::{
+    ptr::addr_of_mut,
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+use rustsbi::{SbiRet, SharedPtr};
+use spin::Once;
+
+pub(crate) struct Sta {
+    /// 特权软件可用的内存区域。
+    memory: RangeList<NUM_MEMORY_REGION_MAX>,
+    /// `mtime` 的频率。
+    timebase: u64,
+}
+
+static INSTANCE: Once<Sta> = Once::new();
+
+pub(crate) fn init(memory: RangeList<NUM_MEMORY_REGION_MAX>, timebase: u64) {
+    INSTANCE.call_once(|| Sta { memory, timebase });
+}
+
+pub(crate) fn get() -> &'static Sta {
+    INSTANCE.wait()
+}
+
+/// 共享内存中的偷取时间记录。
+#[repr(C)]
+struct StaRecord {
+    /// 更新期间为奇数。
+    sequence: u32,
+    flags: u32,
+    /// 被偷取的时间，单位是纳秒。
+    steal: u64,
+    preempted: u8,
+    pad: [u8; 47],
+}
+
+const LEN_RECORD: usize = core::mem::size_of::<StaRecord>();
+const _: () = assert!(LEN_RECORD == 64);
+
+/// 每个 hart 的偷取时间记录状态。
+pub(crate) struct StaState {
+    /// 记录的物理地址，未设置时为 `None`。
+    shmem: Option<usize>,
+}
+
+impl StaState {
+    pub const fn new() -> Self {
+        Self { shmem: None }
+    }
+}
+
+impl rustsbi::Sta for Sta {
+    fn set_shmem(&self, shmem: SharedPtr<[u8; 64]>, flags: usize) -> SbiRet {
+        if flags != 0 {
+            return SbiRet::invalid_param();
+        }
+        let (lo, hi) = (shmem.phys_addr_lo(), shmem.phys_addr_hi());
+        // 全 1 表示停用
+        if lo == usize::MAX && hi == usize::MAX {
+            local_sta().shmem = None;
+            return SbiRet::success(0);
+        }
+        if lo % LEN_RECORD != 0 {
+            return SbiRet::invalid_param();
+        }
+        match lo.checked_add(LEN_RECORD) {
+            Some(end) if hi == 0 && self.memory.contains_range(lo, end) => {
+                unsafe { (lo as *mut u8).write_bytes(0, LEN_RECORD) };
+                local_sta().shmem = Some(lo);
+                SbiRet::success(0)
+            }
+            _ => SbiRet::invalid_address(),
+        }
+    }
+}
+
+/// 将从 `since` 时刻到现在的时间记入此 hart 被偷取的时间。
+///
+/// 用于不是由特权软件要求的等待，例如关闭和系统挂起。
+pub(crate) fn steal(since: u64) {
+    let Some(shmem) = local_sta().shmem else {
+        return;
+    };
+    let ticks = clint::read_mtime().saturating_sub(since);
+    let Some(ns) = (ticks as u128 * 1_000_000_000).checked_div(get().timebase as u128) else {
+        return;
+    };
+    let record = shmem as *mut StaRecord;
+    unsafe {
+        let sequence = &*(addr_of_mut!((*record).sequence) as *const AtomicU32);
+        let steal = addr_of_mut!((*record).steal);
+        // 序号为奇数时特权软件会重新读取
+        sequence.fetch_add(1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        steal.write_volatile(steal.read_volatile().wrapping_add(ns as u64));
+        fence(Ordering::Release);
+        sequence.fetch_add(1, Ordering::Relaxed);
+    }
+}
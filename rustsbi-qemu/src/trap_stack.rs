@@ -1,66 +1,226 @@
-﻿use crate::{fast_handler, hart_id, Supervisor, LEN_STACK_PER_HART, NUM_HART_MAX};
-use core::{mem::forget, ptr::NonNull};
+﻿use crate::{
+    cppc::CppcState, dbtr::DbtrState, fast_handler, fwft::FwftState, hart_id, pmu::PmuState,
+    rfence::Mailbox, sse::SseState, sta::StaState, Supervisor, GENESIS, LEN_STACK_PER_HART,
+    NUM_HART_MAX,
+};
+use core::{
+    mem::forget,
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use fast_trap::{FlowContext, FreeTrapStack};
 use hsm_cell::{HsmCell, LocalHsmCell, RemoteHsmCell};
+use rustsbi::HartMask;
 
 /// 栈空间。
 #[link_section = ".bss.uninit"]
 static mut ROOT_STACK: [Stack; NUM_HART_MAX] = [Stack::ZERO; NUM_HART_MAX];
 
+/// 启动栈，全局初始化在这个栈上完成。
+#[link_section = ".bss.uninit"]
+static mut BOOT_STACK: Stack = Stack::ZERO;
+
+/// 每个栈所属硬件线程的编号，升序排列。
+static mut HART_IDS: [u32; NUM_HART_MAX] = [0; NUM_HART_MAX];
+
+/// 硬件线程表的长度，为 0 表示硬件线程表尚未建立。
+static NUM_HARTS: AtomicU32 = AtomicU32::new(0);
+
+/// 栈的长度用于从栈地址计算序号。
+const STACK_SHIFT: u32 = LEN_STACK_PER_HART.trailing_zeros();
+const _: () = assert!(LEN_STACK_PER_HART.is_power_of_two());
+
 /// 定位每个 hart 的栈。
+///
+/// 认领到全局初始化的 hart 使用启动栈，并将 a2 置为非零；
+/// 其他 hart 等待硬件线程表建立后使用自己的栈，a2 置零。
 #[naked]
 pub(crate) unsafe extern "C" fn locate() {
     core::arch::asm!(
-        "   la   sp, {stack}
+        "   la   t0, {genesis}
+            amoswap.w.aqrl a2, zero, (t0)
+            beqz a2, 1f
+            la   sp, {boot_stack}
             li   t0, {per_hart_stack_size}
-            csrr t1, mhartid
-            addi t1, t1,  1
-         1: add  sp, sp, t0
-            addi t1, t1, -1
-            bnez t1, 1b
+            add  sp, sp, t0
+            ret
+        ",
+        // 等待硬件线程表
+        "1: la   t0, {num_harts}
+         2: lw   t1, (t0)
+            beqz t1, 2b
+            fence r, rw
+        ",
+        // 在硬件线程表中查找
+        "   csrr t2, mhartid
+            la   t3, {hart_ids}
+            li   t4, 0
+         3: lw   t5, (t3)
+            beq  t5, t2, 5f
+            addi t3, t3, 4
+            addi t4, t4, 1
+            bne  t4, t1, 3b
+        ",
+        // 不在硬件线程表中的 hart 不启动
+        "4: wfi
+            j    4b
+        ",
+        "5: la   sp, {stack}
+            addi t4, t4, 1
+            slli t4, t4, {stack_shift}
+            add  sp, sp, t4
             call t1, {move_stack}
             ret
         ",
         per_hart_stack_size = const LEN_STACK_PER_HART,
+        stack_shift         = const STACK_SHIFT,
+        genesis             =   sym GENESIS,
+        boot_stack          =   sym BOOT_STACK,
+        num_harts           =   sym NUM_HARTS,
+        hart_ids            =   sym HART_IDS,
         stack               =   sym ROOT_STACK,
         move_stack          =   sym fast_trap::reuse_stack_for_trap,
         options(noreturn),
     )
 }
 
+/// 建立硬件线程表，硬件线程编号对应的栈由此确定。
+pub(crate) fn init_harts(hart_ids: &[u32]) {
+    let ids = unsafe { &mut HART_IDS[..hart_ids.len()] };
+    ids.copy_from_slice(hart_ids);
+    ids.sort_unstable();
+    NUM_HARTS.store(ids.len() as _, Ordering::Release);
+}
+
+/// 遍历所有硬件线程的编号。
+pub(crate) fn hart_ids() -> impl Iterator<Item = usize> {
+    let len = NUM_HARTS.load(Ordering::Acquire) as usize;
+    unsafe { HART_IDS[..len].iter().map(|&id| id as _) }
+}
+
+/// 选出 `hart_mask` 指定的所有硬件线程编号。
+///
+/// `hart_mask_base` 为 -1 时选出所有硬件线程；
+/// `hart_mask` 指定了不存在的硬件线程时返回 `None`。
+pub(crate) fn select_harts(hart_mask: HartMask) -> Option<impl Iterator<Item = usize>> {
+    let (mask, base) = hart_mask.into_inner();
+    let broadcast = base == usize::MAX;
+    let selected = move |id: usize| {
+        broadcast
+            || id
+                .checked_sub(base)
+                .filter(|&i| i < usize::BITS as usize)
+                .map_or(false, |i| mask & (1 << i) != 0)
+    };
+    // 每个置位的比特都要对应一个存在的硬件线程
+    (broadcast || hart_ids().filter(|&id| selected(id)).count() == mask.count_ones() as usize)
+        .then(|| hart_ids().filter(move |&id| selected(id)))
+}
+
+/// 硬件线程编号对应的栈序号。
+pub(crate) fn slot(hart_id: usize) -> Option<usize> {
+    let len = NUM_HARTS.load(Ordering::Acquire) as usize;
+    let hart_id = u32::try_from(hart_id).ok()?;
+    unsafe { HART_IDS[..len].binary_search(&hart_id).ok() }
+}
+
+/// 此 hart 的上下文。
+#[inline]
+fn local_context() -> &'static mut HartContext {
+    // 只有在硬件线程表中的 hart 会进入这里
+    let slot = slot(hart_id()).unwrap();
+    unsafe { ROOT_STACK.get_unchecked_mut(slot).hart_context() }
+}
+
 /// 预备陷入栈。
 pub(crate) fn prepare_for_trap() {
-    unsafe { ROOT_STACK.get_unchecked_mut(hart_id()).load_as_stack() };
+    let slot = slot(hart_id()).unwrap();
+    unsafe { ROOT_STACK.get_unchecked_mut(slot).load_as_stack() };
 }
 
 /// 获取此 hart 的 local hsm 对象。
 pub(crate) fn local_hsm() -> LocalHsmCell<'static, Supervisor> {
-    unsafe {
-        ROOT_STACK
-            .get_unchecked_mut(hart_id())
-            .hart_context()
-            .hsm
-            .local()
-    }
+    unsafe { local_context().hsm.local() }
 }
 
 /// 获取此 hart 的 remote hsm 对象。
 pub(crate) fn local_remote_hsm() -> RemoteHsmCell<'static, Supervisor> {
-    unsafe {
-        ROOT_STACK
-            .get_unchecked_mut(hart_id())
-            .hart_context()
-            .hsm
-            .remote()
-    }
+    local_context().hsm.remote()
+}
+
+/// 获取此 hart 的 CPPC 寄存器。
+pub(crate) fn local_cppc() -> &'static mut CppcState {
+    &mut local_context().cppc
+}
+
+/// 获取此 hart 的触发器状态。
+pub(crate) fn local_dbtr() -> &'static mut DbtrState {
+    &mut local_context().dbtr
+}
+
+/// 获取此 hart 的固件特性状态。
+pub(crate) fn local_fwft() -> &'static mut FwftState {
+    &mut local_context().fwft
+}
+
+/// 获取此 hart 的性能计数器状态。
+pub(crate) fn local_pmu() -> &'static mut PmuState {
+    &mut local_context().pmu
+}
+
+/// 获取此 hart 的软件事件状态。
+pub(crate) fn local_sse() -> &'static SseState {
+    &local_context().sse
+}
+
+/// 获取任意 hart 的软件事件状态。
+pub(crate) fn remote_sse(hart_id: usize) -> Option<&'static SseState> {
+    slot(hart_id).map(|slot| unsafe { &ROOT_STACK[slot].hart_context().sse })
+}
+
+/// 获取此 hart 的偷取时间记录状态。
+pub(crate) fn local_sta() -> &'static mut StaState {
+    &mut local_context().sta
+}
+
+/// 记录此 hart 模拟的一次非对齐访存，返回累计次数。
+pub(crate) fn count_misaligned() -> usize {
+    let hart = local_context();
+    hart.misaligned += 1;
+    hart.misaligned
+}
+
+/// 记录此 hart 从不可恢复挂起中唤醒后进入特权软件的位置。
+pub(crate) fn set_resume(resume: Supervisor) {
+    local_context().resume = Some(resume);
+}
+
+/// 取出此 hart 从不可恢复挂起中唤醒后进入特权软件的位置。
+pub(crate) fn take_resume() -> Option<Supervisor> {
+    local_context().resume.take()
+}
+
+/// 获取此 hart 的邮箱。
+pub(crate) fn local_mailbox() -> &'static Mailbox {
+    &local_context().mailbox
+}
+
+/// 获取任意 hart 的邮箱。
+pub(crate) fn remote_mailbox(hart_id: usize) -> Option<&'static Mailbox> {
+    slot(hart_id).map(|slot| unsafe { &ROOT_STACK[slot].hart_context().mailbox })
 }
 
 /// 获取任意 hart 的 remote hsm 对象。
 pub(crate) fn remote_hsm(hart_id: usize) -> Option<RemoteHsmCell<'static, Supervisor>> {
-    unsafe {
-        ROOT_STACK
-            .get_mut(hart_id)
-            .map(|x| x.hart_context().hsm.remote())
+    slot(hart_id).map(|slot| unsafe { ROOT_STACK[slot].hart_context().hsm.remote() })
+}
+
+/// 将所有 hart 的 hsm 状态重置为关闭。
+///
+/// 用于热重启，调用时其他 hart 不能访问自己的 hsm 对象。
+pub(crate) fn reset_hsm() {
+    for stack in unsafe { ROOT_STACK.iter_mut() } {
+        stack.hart_context().hsm = HsmCell::new();
     }
 }
 
@@ -105,12 +265,39 @@ struct HartContext {
     /// 陷入上下文。
     trap: FlowContext,
     hsm: HsmCell<Supervisor>,
+    /// CPPC 寄存器。
+    cppc: CppcState,
+    /// 固件特性状态。
+    fwft: FwftState,
+    /// 调试触发器。
+    dbtr: DbtrState,
+    /// 性能计数器状态。
+    pmu: PmuState,
+    /// 模拟的非对齐访存次数。
+    misaligned: usize,
+    /// 核间消息。
+    mailbox: Mailbox,
+    /// 偷取时间记录。
+    sta: StaState,
+    /// 软件事件。
+    sse: SseState,
+    /// 不可恢复挂起的恢复位置。
+    resume: Option<Supervisor>,
 }
 
 impl HartContext {
     #[inline]
     fn init(&mut self) {
         self.hsm = HsmCell::new();
+        self.cppc = CppcState::new();
+        self.fwft = FwftState::new();
+        self.dbtr = DbtrState::new();
+        self.pmu = PmuState::new();
+        self.misaligned = 0;
+        self.mailbox = Mailbox::new();
+        self.sta = StaState::new();
+        self.sse = SseState::new();
+        self.resume = None;
     }
 
     #[inline]
@@ -17,7 +17,7 @@ pub(crate) unsafe extern "C" fn trap_vec() {
         "j {default}", // exception
         "j {default}", // supervisor software
         "j {default}", // reserved
-        "j {msoft} ",  // machine    software
+        "j {default}", // machine    software
         "j {default}", // reserved
         "j {default}", // supervisor timer
         "j {default}", // reserved
@@ -29,7 +29,6 @@ pub(crate) unsafe extern "C" fn trap_vec() {
         ".option pop",
         default = sym trap_entry,
         mtimer  = sym mtimer,
-        msoft   = sym msoft,
         options(noreturn)
     )
 }
@@ -84,47 +83,3 @@ unsafe extern "C" fn mtimer() {
         options(noreturn)
     )
 }
-
-/// machine soft 中断代理
-///
-/// # Safety
-///
-/// 裸函数。
-#[naked]
-unsafe extern "C" fn msoft() {
-    asm!(
-        // 换栈：
-        // sp      : M sp
-        // mscratch: S sp
-        "   csrrw sp, mscratch, sp",
-        // 保护
-        "   addi sp, sp, -3*8
-            sd   ra, 0*8(sp)
-            sd   a0, 1*8(sp)
-            sd   a1, 2*8(sp)
-        ",
-        // 清除 msip 设置 ssip
-        "   la   a0, {clint_ptr}
-            ld   a0, (a0)
-            csrr a1, mhartid
-            call {clear_msip}
-            csrrsi zero, mip, 1 << 1
-        ",
-        // 恢复
-        "   ld   ra, 0*8(sp)
-            ld   a0, 1*8(sp)
-            ld   a1, 2*8(sp)
-            addi sp, sp,  3*8
-        ",
-        // 换栈：
-        // sp      : S sp
-        // mscratch: M sp
-        "   csrrw sp, mscratch, sp",
-        // 返回
-        "   mret",
-        clint_ptr  = sym CLINT,
-        //               Clint::clear_msip_naked(&self, hart_idx)
-        clear_msip = sym Clint::clear_msip_naked,
-        options(noreturn)
-    )
-}
@@ -1,21 +1,159 @@
-﻿use core::ptr::null;
-use spin::lock_api::Mutex;
-use uart16550::Uart16550;
+﻿use crate::{console::ConsoleDevice, device_tree::ConsoleInfo};
+use spin::Once;
 
-pub(crate) static UART: Mutex<Uart16550Map> = Mutex::new(Uart16550Map(null()));
+static INSTANCE: Once<Uart16550> = Once::new();
 
-pub(crate) fn init(base: usize) {
-    *UART.lock() = Uart16550Map(base as _);
+/// 按设备树描述的寄存器布局和 `/chosen/stdout-path` 中的串口参数初始化串口。
+///
+/// 缺少时钟频率或波特率时保留原有的分频设置；`reg-shift` 使寄存器超出设备区域时按 0 处理。
+pub(crate) fn init(info: &ConsoleInfo) -> &'static dyn ConsoleDevice {
+    INSTANCE.call_once(|| {
+        let shift = Some(info.reg_shift)
+            .filter(|&shift| {
+                LSR.checked_shl(shift)
+                    .is_some_and(|off| off < info.reg.len())
+            })
+            .unwrap_or(0);
+        let uart = Uart16550 {
+            base: info.reg.start,
+            shift,
+            width: info.reg_io_width,
+        };
+        let (baud, lcr) = parse_options(info.options.as_bytes());
+        let divisor = baud
+            .or(info.current_speed)
+            .zip(info.clock_frequency)
+            .and_then(|(baud, clock)| divisor(clock, baud));
+        uart.write(IER, 0);
+        if let Some(divisor) = divisor {
+            uart.write(LCR, LCR_DLAB);
+            uart.write(DLL, divisor as _);
+            uart.write(DLM, (divisor >> 8) as _);
+        }
+        uart.write(LCR, lcr);
+        uart.write(FCR, FCR_ENABLE | FCR_CLEAR_RX | FCR_CLEAR_TX);
+        // OUT2 在一些实现上控制中断输出
+        uart.write(MCR, MCR_DTR | MCR_RTS | MCR_OUT2);
+        uart
+    })
 }
 
-pub struct Uart16550Map(*const Uart16550<u8>);
+/// 寄存器编号。
+const RBR_THR: usize = 0;
+const IER: usize = 1;
+const FCR: usize = 2;
+const LCR: usize = 3;
+const MCR: usize = 4;
+const LSR: usize = 5;
+/// 设置 `LCR_DLAB` 时访问分频锁存器。
+const DLL: usize = 0;
+const DLM: usize = 1;
 
-unsafe impl Send for Uart16550Map {}
-unsafe impl Sync for Uart16550Map {}
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+const FCR_CLEAR_TX: u8 = 1 << 2;
+const LCR_PARITY: u8 = 1 << 3;
+const LCR_EVEN_PARITY: u8 = 1 << 4;
+const LCR_DLAB: u8 = 1 << 7;
+const MCR_DTR: u8 = 1 << 0;
+const MCR_RTS: u8 = 1 << 1;
+const MCR_OUT2: u8 = 1 << 3;
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+/// 解析 `<baud>{<parity>{<bits>}}` 形式的串口参数，返回波特率和 LCR。
+///
+/// 未给出的部分按 8 位数据位、无校验、1 位停止位处理。
+fn parse_options(options: &[u8]) -> (Option<u32>, u8) {
+    let digits = options.iter().take_while(|c| c.is_ascii_digit()).count();
+    let baud = options[..digits]
+        .iter()
+        .try_fold(0u32, |acc, c| {
+            acc.checked_mul(10)?.checked_add((c - b'0') as _)
+        })
+        .filter(|baud| *baud != 0);
+    let mut rest = options[digits..].iter();
+    let parity = match rest.next() {
+        Some(b'o') => LCR_PARITY,
+        Some(b'e') => LCR_PARITY | LCR_EVEN_PARITY,
+        _ => 0,
+    };
+    let bits = match rest.next() {
+        Some(c @ b'5'..=b'8') => c - b'5',
+        _ => 3,
+    };
+    (baud, parity | bits)
+}
+
+/// 按 16 倍过采样计算最接近的分频系数。
+fn divisor(clock: u32, baud: u32) -> Option<u16> {
+    if baud == 0 {
+        return None;
+    }
+    let divisor = (clock as u64 + 8 * baud as u64) / (16 * baud as u64);
+    u16::try_from(divisor).ok().filter(|d| *d != 0)
+}
+
+/// 兼容 16550 的串口。
+pub(crate) struct Uart16550 {
+    base: usize,
+    /// 寄存器编号左移的位数。
+    shift: u32,
+    /// 寄存器访问宽度。
+    width: u32,
+}
+
+impl Uart16550 {
+    #[inline]
+    fn read(&self, reg: usize) -> u8 {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            match self.width {
+                4 => (addr as *const u32).read_volatile() as _,
+                2 => (addr as *const u16).read_volatile() as _,
+                _ => (addr as *const u8).read_volatile(),
+            }
+        }
+    }
 
-impl Uart16550Map {
     #[inline]
-    pub fn get(&self) -> &Uart16550<u8> {
-        unsafe { &*self.0 }
+    fn write(&self, reg: usize, val: u8) {
+        let addr = self.base + (reg << self.shift);
+        unsafe {
+            match self.width {
+                4 => (addr as *mut u32).write_volatile(val as _),
+                2 => (addr as *mut u16).write_volatile(val as _),
+                _ => (addr as *mut u8).write_volatile(val),
+            }
+        }
+    }
+}
+
+impl ConsoleDevice for Uart16550 {
+    #[inline]
+    fn receive(&self) -> Option<u8> {
+        (self.read(LSR) & LSR_DATA_READY != 0).then(|| self.read(RBR_THR))
+    }
+
+    #[inline]
+    fn transmit(&self, c: u8) -> bool {
+        if self.read(LSR) & LSR_THR_EMPTY == 0 {
+            return false;
+        }
+        self.write(RBR_THR, c);
+        true
+    }
+
+    fn set_interrupts(&self, rx: bool, tx: bool) {
+        let mut ier = 0;
+        if rx {
+            ier |= IER_RDA;
+        }
+        if tx {
+            ier |= IER_THRE;
+        }
+        self.write(IER, ier);
     }
 }
@@ -0,0 +1,114 @@
+use crate::riscv_spec::mstatus;
+use core::arch::asm;
+
+/// 以特权软件视角访存时发生的陷入。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TrapInfo {
+    pub cause: usize,
+    pub tval: usize,
+}
+
+/// 访存未发生陷入时 t0 保持的值。
+const NO_TRAP: usize = usize::MAX;
+
+/// 以特权软件视角执行一条访存指令。
+///
+/// 临时设置 `mstatus.MPRV` 使访存经过特权软件的地址翻译，
+/// 并临时替换 `mtvec` 以捕获访存异常。
+macro_rules! unprivileged {
+    ($insn:literal, $dir:ident($val:expr), $addr:expr, $flags:expr) => {{
+        let cause: usize;
+        let tval: usize;
+        asm!(
+            "   csrr  {mepc},    mepc
+                csrrw {mtvec},   mtvec,   {mtvec}
+                csrrs {mstatus}, mstatus, {flags}
+            ",
+            ".option push",
+            ".option norvc",
+            concat!($insn, " {val}, ({addr})"),
+            ".option pop",
+            "   csrw  mstatus, {mstatus}
+                csrw  mtvec,   {mtvec}
+                csrw  mepc,    {mepc}
+            ",
+            mepc    = out(reg) _,
+            mtvec   = inout(reg) trap_handler as usize => _,
+            mstatus = out(reg) _,
+            flags   = in(reg) $flags,
+            addr    = in(reg) $addr,
+            val     = $dir(reg) $val,
+            inout("t0") NO_TRAP => cause,
+            out("t1") tval,
+            out("t2") _,
+        );
+        if cause == NO_TRAP {
+            Ok(())
+        } else {
+            Err(TrapInfo { cause, tval })
+        }
+    }};
+}
+
+/// 从特权软件地址空间读一个字节。
+#[inline]
+pub(crate) fn load_u8(addr: usize) -> Result<u8, TrapInfo> {
+    let val: usize;
+    unsafe { unprivileged!("lbu", out(val), addr, mstatus::MPRV) }.map(|()| val as _)
+}
+
+/// 向特权软件地址空间写一个字节。
+#[inline]
+pub(crate) fn store_u8(addr: usize, val: u8) -> Result<(), TrapInfo> {
+    unsafe { unprivileged!("sb", in(val), addr, mstatus::MPRV) }
+}
+
+/// 从特权软件地址空间取一条指令。
+///
+/// 取指异常转换为对应的指令异常。
+pub(crate) fn load_insn(pc: usize) -> Result<u32, TrapInfo> {
+    #[inline]
+    fn load_u16(addr: usize) -> Result<u16, TrapInfo> {
+        let val: usize;
+        unsafe { unprivileged!("lhu", out(val), addr, mstatus::MPRV | mstatus::MXR) }
+            .map(|()| val as _)
+            .map_err(|e| TrapInfo {
+                cause: match e.cause {
+                    // load access fault -> instruction access fault
+                    5 => 1,
+                    // load page fault -> instruction page fault
+                    13 => 12,
+                    cause => cause,
+                },
+                tval: addr,
+            })
+    }
+    let lo = load_u16(pc)? as u32;
+    if lo & 0b11 != 0b11 {
+        Ok(lo)
+    } else {
+        Ok(lo | (load_u16(pc + 2)? as u32) << 16)
+    }
+}
+
+/// 访存异常处理，也用于探测 CSR 是否存在。
+///
+/// 将异常原因保存到 t0，异常值保存到 t1，并跳过引起异常的指令。
+///
+/// # Safety
+///
+/// 裸函数。
+#[naked]
+pub(crate) unsafe extern "C" fn trap_handler() {
+    asm!(
+        ".align 2",
+        "   csrr t0, mcause
+            csrr t1, mtval
+            csrr t2, mepc
+            addi t2, t2, 4
+            csrw mepc, t2
+            mret
+        ",
+        options(noreturn)
+    )
+}
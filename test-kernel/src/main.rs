@@ -6,10 +6,19 @@
 #[macro_use]
 extern crate rcore_console;
 
-use core::{arch::asm, ptr::null};
+use core::{
+    arch::asm,
+    ptr::null,
+    sync::atomic::{AtomicU32, Ordering},
+};
 use sbi_testing::sbi;
 use uart16550::Uart16550;
 
+const STACK_SIZE: usize = 16384; // 16 KiB
+
+#[link_section = ".bss.uninit"]
+static mut STACK: [u8; STACK_SIZE] = [0u8; STACK_SIZE];
+
 /// 内核入口。
 ///
 /// # Safety
@@ -19,11 +28,6 @@ use uart16550::Uart16550;
 #[no_mangle]
 #[link_section = ".text.entry"]
 unsafe extern "C" fn _start(hartid: usize, device_tree_paddr: usize) -> ! {
-    const STACK_SIZE: usize = 16384; // 16 KiB
-
-    #[link_section = ".bss.uninit"]
-    static mut STACK: [u8; STACK_SIZE] = [0u8; STACK_SIZE];
-
     asm!(
         "la sp, {stack} + {stack_size}",
         "j  {main}",
@@ -34,6 +38,23 @@ unsafe extern "C" fn _start(hartid: usize, device_tree_paddr: usize) -> ! {
     )
 }
 
+/// 系统挂起后的恢复入口。
+///
+/// # Safety
+///
+/// 裸函数。
+#[naked]
+unsafe extern "C" fn resume(hartid: usize, opaque: usize) -> ! {
+    asm!(
+        "la sp, {stack} + {stack_size}",
+        "j  {main}",
+        stack_size = const STACK_SIZE,
+        stack      =   sym STACK,
+        main       =   sym rust_resume,
+        options(noreturn),
+    )
+}
+
 extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
     extern "C" {
         static mut sbss: u64;
@@ -51,6 +72,7 @@ extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
         smp,
         frequency,
         uart,
+        hypervisor,
     } = BoardInfo::parse(dtb_pa);
     unsafe { UART = Uart16550Map(uart as _) };
     rcore_console::init_console(&Console);
@@ -69,13 +91,23 @@ extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
 | dtb physical address  | {dtb_pa:#20x} |
 ------------------------------------------------"
     );
+    // 位图最多容纳 usize::BITS 个 hart
+    let hart_mask = match smp {
+        smp if smp >= usize::BITS as usize => usize::MAX,
+        smp => (1 << smp) - 1,
+    };
     let testing = sbi_testing::Testing {
         hartid,
-        hart_mask: (1 << smp) - 1,
+        hart_mask,
         hart_mask_base: 0,
         delay: frequency,
     };
-    if testing.test() {
+    if testing.test()
+        && test_hfence(hypervisor)
+        && test_getchar()
+        && test_sse(hartid)
+        && test_susp(frequency)
+    {
         sbi::system_reset(sbi::Shutdown, sbi::NoReason);
     } else {
         sbi::system_reset(sbi::Shutdown, sbi::SystemFailure);
@@ -83,6 +115,217 @@ extern "C" fn rust_main(hartid: usize, dtb_pa: usize) -> ! {
     unreachable!()
 }
 
+/// 测试远程 `hfence` 调用。
+///
+/// 以所有 hart 为目标，处理器实现了 H 扩展时应该全部成功，否则应该全部返回不支持。
+fn test_hfence(hypervisor: bool) -> bool {
+    use sbi::{HartMask, SbiRet};
+
+    const TARGET: &str = "test-kernel";
+    log::info!(target: TARGET, "Testing `RFNC` hfence, hypervisor = {hypervisor}");
+    let mask = HartMask::from_mask_base(0, usize::MAX);
+    let expected = if hypervisor {
+        SbiRet::success(0).error
+    } else {
+        SbiRet::not_supported().error
+    };
+    let cases = [
+        ("hfence.gvma", sbi::remote_hfence_gvma(mask, 0, 0)),
+        (
+            "hfence.gvma.vmid",
+            sbi::remote_hfence_gvma_vmid(mask, 0x8000_0000, 0x3000, 1),
+        ),
+        ("hfence.vvma", sbi::remote_hfence_vvma(mask, 0x1000, 0x1000)),
+        (
+            "hfence.vvma.asid",
+            sbi::remote_hfence_vvma_asid(mask, 0, usize::MAX, 1),
+        ),
+    ];
+    let mut result = true;
+    for (name, ret) in cases {
+        if ret.error != expected {
+            log::error!(target: TARGET, "remote {name} returns {ret:?}");
+            result = false;
+        }
+    }
+    if result {
+        log::info!(target: TARGET, "Sbi `RFNC` hfence test pass");
+    }
+    result
+}
+
+/// 测试 legacy `console_getchar`，没有输入时应该立即返回 -1。
+fn test_getchar() -> bool {
+    const TARGET: &str = "test-kernel";
+    const LEGACY_CONSOLE_GETCHAR: usize = 2;
+    log::info!(target: TARGET, "Testing legacy `console_getchar`");
+    let ret: isize;
+    unsafe {
+        asm!(
+            "ecall",
+            lateout("a0") ret,
+            lateout("a1") _,
+            in("a7") LEGACY_CONSOLE_GETCHAR,
+        )
+    };
+    if ret == -1 {
+        log::info!(target: TARGET, "Sbi legacy `console_getchar` test pass");
+        true
+    } else {
+        log::error!(target: TARGET, "console_getchar returns {ret} without input");
+        false
+    }
+}
+
+/// 特权态软件事件扩展。
+struct Sse;
+
+const EID_SSE: usize = 0x53_5345;
+
+impl sbi::Extension for Sse {
+    #[inline]
+    fn extension_id(&self) -> usize {
+        EID_SSE
+    }
+}
+
+/// 软件注入的本地事件。
+const SSE_LOCAL_SOFTWARE: usize = 0xffff_0000;
+
+/// 事件处理程序置位的标记。
+static SSE_FLAG: AtomicU32 = AtomicU32::new(0);
+
+/// 调用特权态软件事件扩展。
+///
+/// sbi-rt 尚未支持这个扩展。
+fn sse_call(fid: usize, arg0: usize, arg1: usize, arg2: usize) -> sbi::SbiRet {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") EID_SSE,
+        )
+    };
+    sbi::SbiRet { error, value }
+}
+
+/// 软件事件处理程序。
+///
+/// a6 是 hart 编号，a7 是注册时的参数，即 [`SSE_FLAG`] 的地址。
+/// 只使用 a6 和 a7，它们在完成事件时由 SBI 恢复。
+///
+/// # Safety
+///
+/// 裸函数。
+#[naked]
+unsafe extern "C" fn sse_handler() -> ! {
+    asm!(
+        "   li   a6, 1
+            sw   a6, 0(a7)
+            li   a7, {eid}
+            li   a6, {complete}
+            ecall
+        ",
+        eid      = const EID_SSE,
+        complete = const 6,
+        options(noreturn),
+    )
+}
+
+/// 测试特权态软件事件，向自己注入本地事件并检查处理程序被调用。
+///
+/// 扩展不存在时跳过测试。
+fn test_sse(hartid: usize) -> bool {
+    const TARGET: &str = "test-kernel";
+    const REGISTER: usize = 2;
+    const UNREGISTER: usize = 3;
+    const ENABLE: usize = 4;
+    const DISABLE: usize = 5;
+    const INJECT: usize = 7;
+    const HART_UNMASK: usize = 8;
+    const HART_MASK: usize = 9;
+    if sbi::probe_extension(Sse).is_unavailable() {
+        log::warn!(target: TARGET, "Sbi `SSE` not exist, skipped");
+        return true;
+    }
+    log::info!(target: TARGET, "Testing `SSE`");
+    let flag = &SSE_FLAG as *const _ as usize;
+    let steps = [
+        ("register", REGISTER, sse_handler as usize, flag),
+        ("enable", ENABLE, 0, 0),
+        ("hart unmask", HART_UNMASK, 0, 0),
+        ("inject", INJECT, hartid, 0),
+    ];
+    for (name, fid, arg1, arg2) in steps {
+        let arg0 = if fid == HART_UNMASK {
+            0
+        } else {
+            SSE_LOCAL_SOFTWARE
+        };
+        let ret = sse_call(fid, arg0, arg1, arg2);
+        if ret.error != 0 {
+            log::error!(target: TARGET, "sse {name} returns {ret:?}");
+            return false;
+        }
+    }
+    let handled = SSE_FLAG.load(Ordering::Acquire) == 1;
+    sse_call(HART_MASK, 0, 0, 0);
+    sse_call(DISABLE, SSE_LOCAL_SOFTWARE, 0, 0);
+    sse_call(UNREGISTER, SSE_LOCAL_SOFTWARE, 0, 0);
+    if handled {
+        log::info!(target: TARGET, "Sbi `SSE` test pass");
+    } else {
+        log::error!(target: TARGET, "sse handler not called after inject");
+    }
+    handled
+}
+
+/// 传给系统挂起的参数，恢复时检查。
+const SUSP_OPAQUE: usize = 0x5355_5350;
+
+/// 测试系统挂起，由时钟中断唤醒。
+///
+/// 挂起成功时从 [`resume`] 继续，不会返回；扩展不存在时跳过测试。
+fn test_susp(frequency: u64) -> bool {
+    use riscv::register::{sie, time};
+
+    const TARGET: &str = "test-kernel";
+    if sbi::probe_extension(sbi::Suspend).is_unavailable() {
+        log::warn!(target: TARGET, "Sbi `SUSP` not exist, skipped");
+        return true;
+    }
+    log::info!(target: TARGET, "Testing `SUSP`");
+    // 其他 hart 应该已经全部关闭，0.1 秒后由时钟中断唤醒
+    sbi::set_timer(time::read64() + frequency / 10);
+    unsafe { sie::set_stimer() };
+    let ret = sbi::system_suspend(sbi::SuspendToRam, resume as usize, SUSP_OPAQUE);
+    unsafe { sie::clear_stimer() };
+    sbi::set_timer(u64::MAX);
+    log::error!(target: TARGET, "system suspend returns {ret:?}");
+    false
+}
+
+/// 从系统挂起中恢复，检查恢复状态后结束测试。
+extern "C" fn rust_resume(hartid: usize, opaque: usize) -> ! {
+    use riscv::register::{satp, sie, sstatus};
+
+    const TARGET: &str = "test-kernel";
+    unsafe { sie::clear_stimer() };
+    sbi::set_timer(u64::MAX);
+    if opaque != SUSP_OPAQUE || sstatus::read().sie() || satp::read().bits() != 0 {
+        log::error!(target: TARGET, "hart {hartid} resumed with unexpected state");
+        sbi::system_reset(sbi::Shutdown, sbi::SystemFailure);
+    } else {
+        log::info!(target: TARGET, "Sbi `SUSP` test pass");
+        sbi::system_reset(sbi::Shutdown, sbi::NoReason);
+    }
+    unreachable!()
+}
+
 #[cfg_attr(not(test), panic_handler)]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     let (hart_id, pc): (usize, usize);
@@ -99,6 +342,8 @@ struct BoardInfo {
     smp: usize,
     frequency: u64,
     uart: usize,
+    /// 处理器实现了 H 扩展。
+    hypervisor: bool,
 }
 
 impl BoardInfo {
@@ -109,6 +354,7 @@ impl BoardInfo {
             smp: 0,
             frequency: 0,
             uart: 0,
+            hypervisor: false,
         };
         unsafe {
             Dtb::from_raw_parts_filtered(dtb_pa as _, |e| {
@@ -122,7 +368,12 @@ impl BoardInfo {
                     StepInto
                 } else if ctx.name() == Str::from("cpus") && name.starts_with("cpu@") {
                     ans.smp += 1;
-                    StepOver
+                    // 只从第一个 cpu 节点读取指令集
+                    if ans.smp == 1 {
+                        StepInto
+                    } else {
+                        StepOver
+                    }
                 } else if ctx.name() == Str::from("soc")
                     && (name.starts_with("uart") || name.starts_with("serial"))
                 {
@@ -134,8 +385,10 @@ impl BoardInfo {
             DtbObj::Property(Property::Reg(mut reg)) => {
                 if ctx.name().starts_with("uart") || ctx.name().starts_with("serial") {
                     ans.uart = reg.next().unwrap().start;
+                    StepOut
+                } else {
+                    StepOver
                 }
-                StepOut
             }
             DtbObj::Property(Property::General { name, value }) => {
                 if ctx.name() == Str::from("cpus") && name == Str::from("timebase-frequency") {
@@ -144,6 +397,10 @@ impl BoardInfo {
                         [a, b, c, d, e, f, g, h] => u64::from_be_bytes([a, b, c, d, e, f, g, h]),
                         _ => unreachable!(),
                     };
+                } else if ctx.name().starts_with("cpu@") && name == Str::from("riscv,isa") {
+                    // 形如 `rv64imafdch_zicsr_...`，单字母扩展位于第一个下划线之前
+                    let isa = value.split(|c| *c == b'_' || *c == 0).next().unwrap();
+                    ans.hypervisor = isa.get(4..).map_or(false, |exts| exts.contains(&b'h'));
                 }
                 StepOver
             }
@@ -10,6 +10,9 @@ use std::{
     sync::OnceLock,
 };
 
+// 与 rustsbi-qemu 构建脚本共用 SBI 内存长度的计算
+include!("../../rustsbi-qemu/firmware_len.rs");
+
 fn project() -> &'static Path {
     static PROJECT: OnceLock<&'static Path> = OnceLock::new();
     PROJECT.get_or_init(|| Path::new(std::env!("CARGO_MANIFEST_DIR")).parent().unwrap())
@@ -52,6 +55,18 @@ struct BuildArgs {
     /// Log level.
     #[clap(long)]
     log: Option<String>,
+    /// Maximum number of harts supported by RustSBI-QEMU.
+    #[clap(long)]
+    harts: Option<u16>,
+    /// Supervisor entry address of RustSBI-QEMU (fw_jump mode).
+    #[clap(long)]
+    entry: Option<String>,
+    /// Supervisor embedded into RustSBI-QEMU (fw_payload mode), test, bench or path to a binary file.
+    #[clap(long)]
+    payload: Option<String>,
+    /// Mark the console used by RustSBI-QEMU as reserved in the device tree passed to supervisor.
+    #[clap(long)]
+    reserve_console: bool,
     /// Build in debug mode.
     #[clap(long)]
     debug: bool,
@@ -60,11 +75,39 @@ struct BuildArgs {
 impl BuildArgs {
     fn make(&self, package: &str, binary: bool) -> PathBuf {
         let target = "riscv64imac-unknown-none-elf";
+        let payload = self
+            .payload
+            .as_ref()
+            .filter(|_| package == "rustsbi-qemu")
+            .map(|payload| match payload.to_lowercase().as_str() {
+                "test" | "test-kernel" => self.make("test-kernel", true),
+                "bench" | "bench-kernel" => self.make("bench-kernel", true),
+                _ => fs::canonicalize(payload).unwrap(),
+            });
         Cargo::build()
             .package(package)
             .optional(&self.log, |cargo, log| {
                 cargo.env("LOG", log);
             })
+            .conditional(package != "rustsbi-qemu", |cargo| {
+                let base = self
+                    .entry
+                    .clone()
+                    .unwrap_or_else(|| format!("{:#x}", self.firmware_end()));
+                cargo.env("KERNEL_BASE", base);
+            })
+            .optional(&self.harts, |cargo, harts| {
+                cargo.env("NUM_HART_MAX", harts.to_string());
+            })
+            .optional(&self.entry, |cargo, entry| {
+                cargo.env("SUPERVISOR_ENTRY", entry);
+            })
+            .optional(&payload, |cargo, payload| {
+                cargo.env("PAYLOAD", payload);
+            })
+            .conditional(self.reserve_console, |cargo| {
+                cargo.env("RESERVE_CONSOLE", "1");
+            })
             .conditional(!self.debug, |cargo| {
                 cargo.release();
             })
@@ -88,6 +131,12 @@ impl BuildArgs {
             elf
         }
     }
+
+    /// RustSBI-QEMU 占用的内存结束地址，也是默认的特权软件入口，测试内核链接到这里。
+    fn firmware_end(&self) -> usize {
+        let harts = self.harts.map_or(DEFAULT_NUM_HART_MAX, |n| n as usize);
+        0x8000_0000 + firmware_len(harts)
+    }
 }
 
 #[derive(Args)]
@@ -125,7 +174,10 @@ struct QemuArgs {
     sbi: Option<String>,
     /// Number of hart (SMP for Symmetrical Multiple Processor).
     #[clap(long)]
-    smp: Option<u8>,
+    smp: Option<u16>,
+    /// CPU model and its properties, such as `rv64,h=true` to enable the hypervisor extension.
+    #[clap(long)]
+    cpu: Option<String>,
     /// Port for gdb to connect. If set, qemu will block and wait gdb to connect.
     #[clap(long)]
     gdb: Option<u16>,
@@ -133,27 +185,41 @@ struct QemuArgs {
 
 impl QemuArgs {
     fn run(mut self) {
+        // 默认支持的硬件线程数量不够时按 smp 构建
+        if self.build.harts.is_none() {
+            self.build.harts = self.smp.filter(|&smp| smp as usize > DEFAULT_NUM_HART_MAX);
+        }
         let sbi = self.sbi.take().unwrap_or_else(|| "rust".into());
         let sbi = match sbi.to_lowercase().as_str() {
             "rust" | "rustsbi" => self.build.make("rustsbi-qemu", true),
             "open" | "opensbi" => PathBuf::from("default"),
             _ => panic!(),
         };
-        let kernel = self.build.kernel.take().unwrap_or_else(|| "test".into());
-        let kernel = match kernel.to_lowercase().as_str() {
-            "test" | "test-kernel" => self.build.make("test-kernel", true),
-            "bench" | "bench-kernel" => self.build.make("bench-kernel", true),
-            _ => panic!(),
+        // 内嵌特权软件时不需要另外加载
+        let kernel = if self.build.payload.is_some() {
+            None
+        } else {
+            let kernel = self.build.kernel.take().unwrap_or_else(|| "test".into());
+            // 以 ELF 加载到链接地址，QEMU 加载二进制文件时总是放在 0x80200000
+            Some(match kernel.to_lowercase().as_str() {
+                "test" | "test-kernel" => self.build.make("test-kernel", false),
+                "bench" | "bench-kernel" => self.build.make("bench-kernel", false),
+                _ => panic!(),
+            })
         };
         let status = Qemu::system("riscv64")
             .args(["-machine", "virt"])
             .arg("-nographic")
             .arg("-bios")
             .arg(sbi)
-            .arg("-kernel")
-            .arg(kernel)
+            .optional(&kernel, |qemu, kernel| {
+                qemu.arg("-kernel").arg(kernel);
+            })
             .args(["-serial", "mon:stdio"])
             .args(["-smp", &self.smp.unwrap_or(8).to_string()])
+            .optional(&self.cpu, |qemu, cpu| {
+                qemu.args(["-cpu", cpu]);
+            })
             .optional(&self.gdb, |qemu, gdb| {
                 qemu.args(["-S", "-gdb", &format!("tcp::{gdb}")]);
             })
@@ -188,3 +254,12 @@ fn package<T: AsRef<str>>(name: Option<T>) -> &'static str {
 fn test() {
     QemuArgs::default().run();
 }
+
+#[test]
+fn test_hypervisor() {
+    QemuArgs {
+        cpu: Some("rv64,h=true".into()),
+        ..Default::default()
+    }
+    .run();
+}